@@ -1,6 +1,10 @@
-use crate::ray::Ray;
-use crate::vec3::Point3;
+use crate::vec3::{Point3, Vec3};
 
+/// Minimum thickness of the bounding box of a flat shape, so that slab tests
+/// still see it.
+const BOX_PADDING: f64 = 1e-4;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug)]
 pub struct AABB {
     pub min: Point3,
@@ -12,19 +16,45 @@ impl AABB {
         AABB { min, max }
     }
 
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) / 2.
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2. * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// Slab test, with the inverse ray direction precomputed by the caller
+    /// (see `Ray::inv_direction`).
     #[inline]
-    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
-        !(0..3).any(|a| {
-            let invd = 1_f64 / r.direction()[a];
-            let mut t0 = invd * (self.min[a] - r.origin()[a]);
-            let mut t1 = invd * (self.max[a] - r.origin()[a]);
-            if invd < 0. {
+    pub fn hit(&self, origin: Point3, inv_dir: Vec3, t_min: f64, t_max: f64) -> bool {
+        self.ray_interval(origin, inv_dir, t_min, t_max).is_some()
+    }
+
+    /// Part of `[t_min, t_max]` where the ray is inside the box.
+    #[inline]
+    pub fn ray_interval(
+        &self,
+        origin: Point3,
+        inv_dir: Vec3,
+        mut t_min: f64,
+        mut t_max: f64,
+    ) -> Option<(f64, f64)> {
+        // The ray has to be inside all three slabs over a common interval
+        for a in 0..3 {
+            let mut t0 = inv_dir[a] * (self.min[a] - origin[a]);
+            let mut t1 = inv_dir[a] * (self.max[a] - origin[a]);
+            if inv_dir[a] < 0. {
                 std::mem::swap(&mut t0, &mut t1);
             };
-            let tmin = if t0 > t_min { t0 } else { t_min };
-            let tmax = if t1 < t_max { t1 } else { t_max };
-            tmax <= tmin
-        })
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
     }
 }
 
@@ -42,24 +72,40 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
     AABB::new(small, big)
 }
 
+/// Box from `min` to `max`, thickened along the axes where it is flat.
+pub fn padded_box(mut min: Point3, mut max: Point3) -> AABB {
+    for a in 0..3 {
+        if max[a] - min[a] < BOX_PADDING {
+            min.e[a] -= BOX_PADDING / 2.;
+            max.e[a] += BOX_PADDING / 2.;
+        }
+    }
+    AABB::new(min, max)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::vec3::Vec3;
+    use crate::ray::Ray;
 
     #[test]
     fn ray_intersects_bbox() {
         let r = Ray::new(Vec3::new(-2., -2., -2.), Vec3::new(1., 1., 1.));
         let bbox = AABB::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
-        assert_eq!(bbox.hit(&r, 0.001, 100.), true);
+        assert!(bbox.hit(r.origin(), r.inv_direction(), 0.001, 100.));
 
         let r = Ray::new(Vec3::new(-2., -2., -2.), Vec3::new(-1., -1., -1.));
         let bbox = AABB::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
-        assert_eq!(bbox.hit(&r, 0.001, 100.), false);
+        assert!(!bbox.hit(r.origin(), r.inv_direction(), 0.001, 100.));
 
         let r = Ray::new(Vec3::new(0.5, 0.5, 0.5), Vec3::new(-1., -1., -1.));
         let bbox = AABB::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
-        assert_eq!(bbox.hit(&r, 0.001, 100.), true);
+        assert!(bbox.hit(r.origin(), r.inv_direction(), 0.001, 100.));
+
+        // Crosses every slab, but never all three at once
+        let r = Ray::new(Vec3::new(-1., 0.5, -2.5), Vec3::new(1., 0., 1.));
+        let bbox = AABB::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
+        assert!(!bbox.hit(r.origin(), r.inv_direction(), 0.001, 100.));
     }
 
     #[test]
@@ -67,7 +113,7 @@ mod tests {
         let r = Ray::new(Vec3::new(0., -0.5, 0.5), Vec3::new(1., 0., 0.));
         let bbox1 = AABB::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
         let bbox2 = AABB::new(Point3::new(-1., -1., -1.), Point3::new(0., 0., 0.));
-        assert_eq!(bbox1.hit(&r, 0.001, 100.), false);
-        assert_eq!(surrounding_box(&bbox1, &bbox2).hit(&r, 0.001, 100.), true);
+        assert!(!bbox1.hit(r.origin(), r.inv_direction(), 0.001, 100.));
+        assert!(surrounding_box(&bbox1, &bbox2).hit(r.origin(), r.inv_direction(), 0.001, 100.));
     }
 }
@@ -0,0 +1,66 @@
+use crate::ray::Ray;
+use crate::vec3::{color, unit_vector, Color};
+
+/// Radiance returned for rays that escape the scene.
+#[derive(Copy, Clone, Debug)]
+pub enum Background {
+    Constant(Color),
+    /// Vertical blend from `bottom` (looking down) to `top` (looking up).
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    /// Black: all the light comes from emissive materials.
+    None,
+}
+
+impl Background {
+    pub fn sky() -> Background {
+        Background::Gradient {
+            bottom: color(1., 1., 1.),
+            top: color(0.5, 0.7, 1.),
+        }
+    }
+
+    pub fn color(&self, r: &Ray) -> Color {
+        match self {
+            Background::Constant(c) => *c,
+            Background::Gradient { bottom, top } => {
+                let unit_direction = unit_vector(&r.direction());
+                let t = 0.5 * (unit_direction.y() + 1.0);
+                *bottom * (1. - t) + *top * t
+            }
+            Background::None => color(0., 0., 0.),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::{point3, Vec3};
+
+    fn looking(direction: Vec3) -> Ray {
+        Ray::new(point3(0., 0., 0.), direction)
+    }
+
+    #[test]
+    fn gradient_blends_from_bottom_to_top() {
+        let (bottom, top) = (color(1., 0.5, 0.), color(0., 0.5, 1.));
+        let background = Background::Gradient { bottom, top };
+        assert_eq!(background.color(&looking(Vec3::new(0., -2., 0.))), bottom);
+        assert_eq!(background.color(&looking(Vec3::new(0., 3., 0.))), top);
+        // Halfway at the horizon
+        let horizon = background.color(&looking(Vec3::new(1., 0., 1.)));
+        assert_eq!(horizon, color(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn constant_ignores_direction() {
+        let c = color(0.1, 0.2, 0.3);
+        for &d in [Vec3::new(0., 1., 0.), Vec3::new(0.3, -1., 2.)].iter() {
+            assert_eq!(Background::Constant(c).color(&looking(d)), c);
+            assert_eq!(Background::None.color(&looking(d)), color(0., 0., 0.));
+        }
+    }
+}
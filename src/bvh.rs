@@ -1,92 +1,461 @@
 use crate::aabb::{surrounding_box, AABB};
 use crate::hittable::{BoxedHittable, HitRecord, Hittable};
 use crate::ray::Ray;
-use rand::Rng;
-use std::cmp::Ordering;
+use crate::vec3::Point3;
+use std::fmt;
 
-pub struct BVHNode {
-    left: Box<dyn Hittable + Send + Sync>,
-    right: Option<Box<dyn Hittable + Send + Sync>>,
+/// Maximum number of objects in a leaf unless splitting it costs more.
+pub const DEFAULT_LEAF_SIZE: usize = 4;
+/// Number of centroid bins evaluated per axis when looking for a split.
+const BIN_COUNT: usize = 12;
+/// SAH costs of visiting a node and of intersecting an object, relative to each other.
+const TRAVERSAL_COST: f64 = 0.125;
+const INTERSECTION_COST: f64 = 1.;
+/// Size of the traversal stack. Deeper nodes are turned into leaves.
+const MAX_DEPTH: usize = 64;
+
+/// Node of a `LinearBVH`, stored in depth-first order.
+struct LinearNode {
     aabb_box: AABB,
+    /// First object of a leaf, or second child of an interior node (the first
+    /// child directly follows its parent).
+    offset: usize,
+    /// Number of objects in a leaf, zero for interior nodes.
+    count: usize,
+    /// Split axis of an interior node, along which the first child comes first.
+    axis: usize,
+}
+
+/// Bounding volume hierarchy flattened into an array of nodes, with the objects
+/// of each leaf stored contiguously. Unbounded objects, such as planes, are
+/// kept out of the tree and tested on every ray.
+pub struct LinearBVH {
+    nodes: Vec<LinearNode>,
+    objects: Vec<BoxedHittable>,
+    unbounded: Vec<BoxedHittable>,
 }
 
-impl Hittable for BVHNode {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.aabb_box.hit(r, t_min, t_max) {
-            None
-        } else {
-            let hit_left = self.left.hit(r, t_min, t_max);
-            let ref_right = self.right.as_ref();
-            hit_left.map_or_else(
-                || ref_right.and_then(|a| a.hit(r, t_min, t_max)),
-                |v| match &self.right {
-                    Some(right) => Some(right.hit(r, t_min, v.t).unwrap_or(v)),
-                    None => Some(v),
-                },
-            )
+impl Hittable for LinearBVH {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Unbounded objects first, so that the tree can skip what they hide
+        let mut closest = None;
+        let mut t_closest = t_max;
+        for object in &self.unbounded {
+            if let Some(rec) = object.hit(r, t_min, t_closest) {
+                t_closest = rec.t;
+                closest = Some(rec);
+            }
+        }
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let origin = r.origin();
+        let inv_dir = r.inv_direction();
+        let dir_is_neg = [inv_dir.x() < 0., inv_dir.y() < 0., inv_dir.z() < 0.];
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut idx = 0;
+        loop {
+            let node = &self.nodes[idx];
+            if node.aabb_box.hit(origin, inv_dir, t_min, t_closest) {
+                if node.count > 0 {
+                    for object in &self.objects[node.offset..node.offset + node.count] {
+                        if let Some(rec) = object.hit(r, t_min, t_closest) {
+                            t_closest = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                } else {
+                    // Visit the child on the near side of the split first
+                    let (near, far) = if dir_is_neg[node.axis] {
+                        (node.offset, idx + 1)
+                    } else {
+                        (idx + 1, node.offset)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    idx = near;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            idx = stack[stack_len];
         }
+        closest
     }
+
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
-        Some(self.aabb_box)
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|node| node.aabb_box)
+    }
+}
+
+/// Shape of a built tree, to compare construction strategies.
+#[derive(Copy, Clone, Debug)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+    /// Expected cost of a ray through the tree, relative to one intersection test.
+    pub sah_cost: f64,
+    /// Objects kept out of the tree and tested on every ray
+    pub unbounded: usize,
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes ({} leaves), depth {}, SAH cost {:.2}",
+            self.nodes, self.leaves, self.depth, self.sah_cost
+        )?;
+        if self.unbounded > 0 {
+            write!(f, ", {} unbounded", self.unbounded)?;
+        }
+        Ok(())
     }
 }
 
-fn sort_closure(axis: usize) -> Box<dyn Fn(&BoxedHittable, &BoxedHittable) -> Ordering> {
-    Box::new(move |a: &BoxedHittable, b: &BoxedHittable| {
-        let box_a = a.bounding_box(0., 0.);
-        let box_b = b.bounding_box(0., 0.);
-        match (box_a, box_b) {
-            (Some(v1), Some(v2)) => v1.min.e[axis].partial_cmp(&v2.min.e[axis]).unwrap(),
-            (_, _) => panic!("No bounding box found"),
+struct BuildItem {
+    object: BoxedHittable,
+    bbox: AABB,
+    centroid: Point3,
+}
+
+/// Candidate split: objects whose centroid falls in a bin up to `bin` go left.
+struct Split {
+    axis: usize,
+    bin: usize,
+    cost: f64,
+}
+
+struct CentroidBounds {
+    min: Point3,
+    max: Point3,
+}
+
+impl CentroidBounds {
+    fn new(items: &[BuildItem]) -> CentroidBounds {
+        let mut bounds = CentroidBounds {
+            min: items[0].centroid,
+            max: items[0].centroid,
+        };
+        for item in &items[1..] {
+            for a in 0..3 {
+                bounds.min.e[a] = bounds.min.e[a].min(item.centroid.e[a]);
+                bounds.max.e[a] = bounds.max.e[a].max(item.centroid.e[a]);
+            }
         }
-    })
+        bounds
+    }
+
+    fn extent(&self, axis: usize) -> f64 {
+        self.max.e[axis] - self.min.e[axis]
+    }
+
+    fn bin(&self, axis: usize, centroid: &Point3) -> usize {
+        let offset = (centroid.e[axis] - self.min.e[axis]) / self.extent(axis);
+        ((offset * BIN_COUNT as f64) as usize).min(BIN_COUNT - 1)
+    }
 }
 
-impl BVHNode {
-    pub fn new(mut objs: Vec<BoxedHittable>, time0: f64, time1: f64) -> BVHNode {
-        let mut rng = rand::thread_rng();
-        let axis = rng.gen_range(0..2);
+fn bounds_of(items: &[BuildItem]) -> AABB {
+    items[1..]
+        .iter()
+        .fold(items[0].bbox, |acc, item| surrounding_box(&acc, &item.bbox))
+}
+
+/// Find the cheapest binned split over the three axes, if any separates the objects.
+fn best_split(items: &[BuildItem], bounds: &CentroidBounds, parent_area: f64) -> Option<Split> {
+    let mut best: Option<Split> = None;
+    for axis in 0..3 {
+        if bounds.extent(axis) <= 0. {
+            continue;
+        }
+        let mut counts = [0usize; BIN_COUNT];
+        let mut boxes: [Option<AABB>; BIN_COUNT] = [None; BIN_COUNT];
+        for item in items {
+            let b = bounds.bin(axis, &item.centroid);
+            counts[b] += 1;
+            boxes[b] = Some(boxes[b].map_or(item.bbox, |bb| surrounding_box(&bb, &item.bbox)));
+        }
+        // Sweep from the right to get the cost of everything above each boundary
+        let mut right_cost = [0.; BIN_COUNT];
+        let mut acc_box: Option<AABB> = None;
+        let mut acc_count = 0;
+        for b in (1..BIN_COUNT).rev() {
+            acc_box = merge(acc_box, boxes[b]);
+            acc_count += counts[b];
+            right_cost[b] = acc_box.map_or(0., |bb| bb.surface_area() * acc_count as f64);
+        }
+        let mut acc_box: Option<AABB> = None;
+        let mut acc_count = 0;
+        for b in 0..BIN_COUNT - 1 {
+            acc_box = merge(acc_box, boxes[b]);
+            acc_count += counts[b];
+            if acc_count == 0 || acc_count == items.len() {
+                continue;
+            }
+            let left_cost = acc_box.map_or(0., |bb| bb.surface_area() * acc_count as f64);
+            let cost =
+                TRAVERSAL_COST + INTERSECTION_COST * (left_cost + right_cost[b + 1]) / parent_area;
+            if best.as_ref().is_none_or(|s| cost < s.cost) {
+                best = Some(Split { axis, bin: b, cost });
+            }
+        }
+    }
+    best
+}
 
-        let obj_span = objs.len();
-        let (left, right) = if obj_span == 1 {
-            let last = objs.pop().unwrap();
-            (last, None)
-        } else if obj_span == 2 {
-            let box_a = objs[0].bounding_box(0., 0.);
-            let box_b = objs[1].bounding_box(0., 0.);
-            let test = match (box_a, box_b) {
-                (Some(v1), Some(v2)) => v1.min.e[axis].partial_cmp(&v2.min.e[axis]).unwrap(),
-                (_, _) => panic!("No bounding box found"),
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+struct Builder {
+    nodes: Vec<LinearNode>,
+    objects: Vec<BoxedHittable>,
+    max_leaf_size: usize,
+}
+
+impl Builder {
+    /// Append the subtree over `items` in depth-first order.
+    fn build(&mut self, mut items: Vec<BuildItem>, depth: usize) {
+        let aabb_box = bounds_of(&items);
+        let n = items.len();
+        let idx = self.nodes.len();
+        self.nodes.push(LinearNode {
+            aabb_box,
+            offset: self.objects.len(),
+            count: n,
+            axis: 0,
+        });
+        if n == 1 || depth + 1 >= MAX_DEPTH {
+            self.objects
+                .extend(items.into_iter().map(|item| item.object));
+            return;
+        }
+        let centroids = CentroidBounds::new(&items);
+        let parent_area = aabb_box.surface_area().max(f64::MIN_POSITIVE);
+        let (axis, left, right): (usize, Vec<BuildItem>, Vec<BuildItem>) =
+            match best_split(&items, &centroids, parent_area) {
+                Some(split)
+                    if n > self.max_leaf_size || split.cost < INTERSECTION_COST * n as f64 =>
+                {
+                    let (left, right) = items
+                        .into_iter()
+                        .partition(|item| centroids.bin(split.axis, &item.centroid) <= split.bin);
+                    (split.axis, left, right)
+                }
+                // All centroids coincide: no plane separates them, so halve the list
+                None if n > self.max_leaf_size => {
+                    let right = items.split_off(n / 2);
+                    (0, items, right)
+                }
+                _ => {
+                    self.objects
+                        .extend(items.into_iter().map(|item| item.object));
+                    return;
+                }
             };
-            let last = objs.pop().unwrap();
-            let bef = objs.pop().unwrap();
-            match test {
-                Ordering::Less => (bef, Some(last)),
-                _ => (last, Some(bef)),
+        self.build(left, depth + 1);
+        let second = self.nodes.len();
+        self.build(right, depth + 1);
+        let node = &mut self.nodes[idx];
+        node.offset = second;
+        node.count = 0;
+        node.axis = axis;
+    }
+}
+
+impl LinearBVH {
+    pub fn new(objs: Vec<BoxedHittable>, time0: f64, time1: f64) -> LinearBVH {
+        LinearBVH::with_leaf_size(objs, time0, time1, DEFAULT_LEAF_SIZE)
+    }
+
+    /// Build the tree with the surface area heuristic, binning object centroids
+    /// along each axis. Leaves hold at most `max_leaf_size` objects, and fewer
+    /// when splitting them is expected to be cheaper.
+    pub fn with_leaf_size(
+        objs: Vec<BoxedHittable>,
+        time0: f64,
+        time1: f64,
+        max_leaf_size: usize,
+    ) -> LinearBVH {
+        let mut items = Vec::with_capacity(objs.len());
+        let mut unbounded = vec![];
+        for object in objs {
+            match object.bounding_box(time0, time1) {
+                Some(bbox) => items.push(BuildItem {
+                    centroid: bbox.centroid(),
+                    bbox,
+                    object,
+                }),
+                None => unbounded.push(object),
             }
-        } else {
-            objs.sort_by(sort_closure(axis));
-            let mid = obj_span / 2;
-            let second_half = objs.split_off(mid);
-            let lef: Box<dyn Hittable + Send + Sync> = Box::new(BVHNode::new(objs, time0, time1));
-            let rig: Box<dyn Hittable + Send + Sync> =
-                Box::new(BVHNode::new(second_half, time0, time1));
-            (lef, Some(rig))
+        }
+        let mut builder = Builder {
+            nodes: Vec::with_capacity(2 * items.len()),
+            objects: Vec::with_capacity(items.len()),
+            max_leaf_size: max_leaf_size.max(1),
+        };
+        if !items.is_empty() {
+            builder.build(items, 0);
+        }
+        LinearBVH {
+            nodes: builder.nodes,
+            objects: builder.objects,
+            unbounded,
+        }
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats {
+            nodes: self.nodes.len(),
+            leaves: 0,
+            depth: 0,
+            sah_cost: 0.,
+            unbounded: self.unbounded.len(),
+        };
+        let root_area = match self.nodes.first() {
+            Some(root) => root.aabb_box.surface_area().max(f64::MIN_POSITIVE),
+            None => return stats,
         };
-        let box_a = left.bounding_box(time0, time1);
-        let box_b = right.as_ref().and_then(|a| a.bounding_box(time0, time1));
-        match (box_a, box_b) {
-            (Some(v1), Some(v2)) => BVHNode {
-                left,
-                right,
-                aabb_box: surrounding_box(&v1, &v2),
-            },
-            (Some(v1), None) => BVHNode {
-                left,
-                right: None,
-                aabb_box: v1,
-            },
-            (_, _) => panic!("No bounding box"),
+        let mut stack = vec![(0, 1)];
+        while let Some((idx, depth)) = stack.pop() {
+            let node = &self.nodes[idx];
+            stats.depth = stats.depth.max(depth);
+            let area = node.aabb_box.surface_area() / root_area;
+            if node.count > 0 {
+                stats.leaves += 1;
+                stats.sah_cost += area * INTERSECTION_COST * node.count as f64;
+            } else {
+                stats.sah_cost += area * TRAVERSAL_COST;
+                stack.push((idx + 1, depth + 1));
+                stack.push((node.offset, depth + 1));
+            }
         }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::material::Lambertian;
+    use crate::shapes::Plane;
+    use crate::utils::random_double;
+    use crate::vec3::{color, point3, random_in_unit_sphere, Vec3};
+
+    fn centers(n: usize, spread: Vec3) -> Vec<Point3> {
+        (0..n)
+            .map(|_| {
+                point3(
+                    random_double() * spread.x(),
+                    random_double() * spread.y(),
+                    random_double() * spread.z(),
+                )
+            })
+            .collect()
+    }
+
+    fn spheres(centers: &[Point3]) -> Vec<BoxedHittable> {
+        centers
+            .iter()
+            .map(|&center| {
+                Box::new(Sphere::new(
+                    center,
+                    0.1,
+                    Lambertian::new(color(0.5, 0.5, 0.5)),
+                )) as BoxedHittable
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_closest_hit() {
+        let centers = centers(200, Vec3::new(4., 4., 4.));
+        let bvh = LinearBVH::new(spheres(&centers), 0., 0.);
+        let objects = spheres(&centers);
+        for _ in 0..500 {
+            // Rays through the scene in all directions
+            let origin = point3(2., 2., 2.) + random_in_unit_sphere() * 6.;
+            let target = point3(random_double(), random_double(), random_double()) * 4.;
+            let r = Ray::new(origin, target - origin);
+            let expected = objects
+                .iter()
+                .filter_map(|obj| obj.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t))
+                .fold(None, |acc: Option<f64>, t| {
+                    Some(acc.map_or(t, |a| a.min(t)))
+                });
+            assert_eq!(bvh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t), expected);
+        }
+    }
+
+    #[test]
+    fn empty_tree() {
+        let bvh = LinearBVH::new(vec![], 0., 0.);
+        let r = Ray::new(point3(0., 0., 0.), Vec3::new(1., 0., 0.));
+        assert!(bvh.hit(&r, 0.001, f64::INFINITY).is_none());
+        assert!(bvh.bounding_box(0., 0.).is_none());
+    }
+
+    #[test]
+    fn splits_along_z() {
+        // Objects spread along z only can't be separated on x or y
+        let bvh =
+            LinearBVH::with_leaf_size(spheres(&centers(64, Vec3::new(0., 0., 100.))), 0., 0., 1);
+        let stats = bvh.stats();
+        assert_eq!(stats.leaves, 64);
+        assert_eq!(stats.nodes, 127);
+        assert!(stats.depth < 16, "depth {}", stats.depth);
+        assert!(stats.sah_cost < 64., "cost {}", stats.sah_cost);
+    }
+
+    #[test]
+    fn respects_leaf_size() {
+        let bvh =
+            LinearBVH::with_leaf_size(spheres(&centers(100, Vec3::new(10., 10., 10.))), 0., 0., 4);
+        assert!(bvh.nodes.iter().all(|node| node.count <= 4));
+        assert_eq!(bvh.objects.len(), 100);
+    }
+
+    #[test]
+    fn keeps_unbounded_objects_aside() {
+        let plane = || {
+            Box::new(Plane::new(
+                point3(0., 0., -2.),
+                Vec3::new(0., 0., 1.),
+                Lambertian::new(color(0.5, 0.5, 0.5)),
+            )) as BoxedHittable
+        };
+        let mut objects = spheres(&[point3(0., 0., 0.), point3(0., 0., -4.)]);
+        objects.push(plane());
+        let bvh = LinearBVH::new(objects, 0., 0.);
+        assert_eq!(bvh.stats().unbounded, 1);
+        assert_eq!(bvh.objects.len(), 2);
+        assert!(bvh.bounding_box(0., 0.).is_none());
+
+        // The plane hides the far sphere, and is hidden by the near one
+        let r = Ray::new(point3(0., 0., 5.), Vec3::new(0., 0., -1.));
+        assert!((bvh.hit(&r, 0.001, f64::INFINITY).unwrap().t - 4.9).abs() < 1e-9);
+        let r = Ray::new(point3(1., 1., 5.), Vec3::new(0., 0., -1.));
+        assert!((bvh.hit(&r, 0.001, f64::INFINITY).unwrap().t - 7.).abs() < 1e-9);
+
+        // Without any bounded object there is no tree at all
+        let plane_only = LinearBVH::new(vec![plane()], 0., 0.);
+        assert!(plane_only.hit(&r, 0.001, f64::INFINITY).is_some());
+        let r = Ray::new(point3(1., 1., 5.), Vec3::new(0., 0., 1.));
+        assert!(plane_only.hit(&r, 0.001, f64::INFINITY).is_none());
     }
 }
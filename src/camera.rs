@@ -1,7 +1,37 @@
 use crate::ray::Ray;
+use crate::utils::random_double;
 use crate::vec3::{cross, random_in_unit_sphere, unit_vector, Point3, Vec3};
 use std::f64::consts;
 
+/// Camera placement and lens, independent of the image aspect ratio.
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    /// Times at which the shutter opens and closes
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+        )
+        .with_shutter(self.time0, self.time1)
+    }
+}
+
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -10,6 +40,8 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     lens_radius: f64,
+    time0: f64,
+    time1: f64,
 }
 
 impl Camera {
@@ -42,15 +74,25 @@ impl Camera {
             u,
             v,
             lens_radius: aperture / 2.,
+            time0: 0.,
+            time1: 0.,
         }
     }
 
+    /// Spread the rays over the times from `time0` to `time1`.
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Camera {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
     pub fn get_ray(&self, u: f64, v: f64) -> Ray {
         let rd = random_in_unit_sphere() * self.lens_radius;
         let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(
+        Ray::with_time(
             self.origin + offset,
             self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin - offset,
+            self.time0 + random_double() * (self.time1 - self.time0),
         )
     }
 }
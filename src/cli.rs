@@ -0,0 +1,189 @@
+use crate::scene::RenderSettings;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: rust_raytrace [OPTIONS] [SCENE]
+
+Render SCENE, which is either the name of a built-in scene (spheres,
+triangles, cornell, cornell_smoke), a scene file (.toml) or a Wavefront mesh (.obj).
+Defaults to the spheres scene.
+
+Options:
+  -W, --width <N>       Image width in pixels
+  -H, --height <N>      Image height in pixels
+  -s, --samples <N>     Samples per pixel
+  -d, --max-depth <N>   Maximum number of bounces per path
+      --bvh-leaf-size <N>
+                        Maximum number of objects per BVH leaf
+  -j, --threads <N>     Number of render threads (default: all cores)
+      --seed <N>        Seed for reproducible renders
+  -o, --output <PATH>   Output image, .ppm, .png, .exr or .hdr (default: PPM on stdout)
+  -h, --help            Print this help
+";
+
+/// Settings given on the command line, overriding those of the scene.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub scene: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub samples: Option<i32>,
+    pub max_depth: Option<i32>,
+    pub bvh_leaf_size: Option<i32>,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Render(Options),
+    Help,
+}
+
+fn parse_positive<T: std::str::FromStr + Default + PartialOrd>(
+    flag: &str,
+    value: &str,
+) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(n) if n > T::default() => Ok(n),
+        _ => Err(format!(
+            "{} expects a positive integer, got '{}'",
+            flag, value
+        )),
+    }
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if let Some(scene) = &options.scene {
+                return Err(format!(
+                    "only one scene can be rendered, got '{}' and '{}'",
+                    scene, arg
+                ));
+            }
+            options.scene = Some(arg);
+            continue;
+        }
+        // Accept both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-W" | "--width" | "-H" | "--height" | "-s" | "--samples" | "-d" | "--max-depth"
+            | "--bvh-leaf-size" | "-j" | "--threads" | "--seed" | "-o" | "--output" => {}
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("{} expects a value", flag)),
+        };
+        match flag.as_str() {
+            "-W" | "--width" => options.width = Some(parse_positive(&flag, &value)?),
+            "-H" | "--height" => options.height = Some(parse_positive(&flag, &value)?),
+            "-s" | "--samples" => options.samples = Some(parse_positive(&flag, &value)?),
+            "-d" | "--max-depth" => options.max_depth = Some(parse_positive(&flag, &value)?),
+            "--bvh-leaf-size" => options.bvh_leaf_size = Some(parse_positive(&flag, &value)?),
+            "-j" | "--threads" => options.threads = Some(parse_positive(&flag, &value)?),
+            "--seed" => {
+                options.seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("--seed expects an integer, got '{}'", value))?,
+                )
+            }
+            _ => options.output = Some(PathBuf::from(value)),
+        }
+    }
+    Ok(Command::Render(options))
+}
+
+impl Options {
+    /// Apply the command line overrides to the settings of a scene. When only
+    /// one of width and height is given, the other follows the scene aspect ratio.
+    pub fn apply(&self, settings: RenderSettings) -> RenderSettings {
+        let aspect_ratio = f64::from(settings.width) / f64::from(settings.height);
+        let (width, height) = match (self.width, self.height) {
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, ((f64::from(w) / aspect_ratio).round() as i32).max(1)),
+            (None, Some(h)) => (((f64::from(h) * aspect_ratio).round() as i32).max(1), h),
+            (None, None) => (settings.width, settings.height),
+        };
+        RenderSettings {
+            width,
+            height,
+            samples: self.samples.unwrap_or(settings.samples),
+            max_depth: self.max_depth.unwrap_or(settings.max_depth),
+            bvh_leaf_size: self.bvh_leaf_size.unwrap_or(settings.bvh_leaf_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let command = parse(&[
+            "-W",
+            "800",
+            "--samples=64",
+            "--seed",
+            "7",
+            "cornell",
+            "-o",
+            "out.ppm",
+            "-j",
+            "4",
+        ])
+        .unwrap();
+        assert_eq!(
+            command,
+            Command::Render(Options {
+                scene: Some("cornell".into()),
+                width: Some(800),
+                samples: Some(64),
+                threads: Some(4),
+                seed: Some(7),
+                output: Some(PathBuf::from("out.ppm")),
+                ..Options::default()
+            })
+        );
+        assert_eq!(parse(&["--help"]).unwrap(), Command::Help);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(
+            parse(&["--width", "0"]).unwrap_err(),
+            "--width expects a positive integer, got '0'"
+        );
+        assert_eq!(parse(&["-s"]).unwrap_err(), "-s expects a value");
+        assert_eq!(parse(&["--fast"]).unwrap_err(), "unknown option '--fast'");
+        assert!(parse(&["a.toml", "b.toml"]).is_err());
+    }
+
+    #[test]
+    fn keeps_scene_aspect_ratio() {
+        let options = Options {
+            width: Some(600),
+            max_depth: Some(8),
+            ..Options::default()
+        };
+        let settings = options.apply(RenderSettings::default());
+        assert_eq!((settings.width, settings.height), (600, 338));
+        assert_eq!(settings.max_depth, 8);
+        assert_eq!(settings.samples, RenderSettings::default().samples);
+    }
+}
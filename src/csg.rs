@@ -0,0 +1,239 @@
+use crate::aabb::{surrounding_box, AABB};
+use crate::hittable::{BoxedHittable, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vec3::Point3;
+
+/// Crossings of both operands closer than this, relative to their ray
+/// parameter, are on a shared surface and taken together.
+const COINCIDENT: f64 = 1e-9;
+
+/// How a `Csg` combines its operands.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// Left operand with the right one carved out
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Solid combining two closed objects, such as a sphere with a hole drilled
+/// through it.
+///
+/// The crossings of both operands along the whole ray are merged in order, to
+/// know at each one whether the ray is inside either operand, and only those
+/// entering or leaving the combined solid are kept. Surfaces of the right
+/// operand carved out of the left one have their normals reversed.
+pub struct Csg {
+    op: CsgOp,
+    left: BoxedHittable,
+    right: BoxedHittable,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: BoxedHittable, right: BoxedHittable) -> Csg {
+        Csg { op, left, right }
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.crossings(r, t_min, t_max).into_iter().next()
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let left = self.left.bounding_box(time0, time1);
+        let right = self.right.bounding_box(time0, time1);
+        match self.op {
+            CsgOp::Union => Some(surrounding_box(&left?, &right?)),
+            CsgOp::Intersection => match (left, right) {
+                (Some(a), Some(b)) => {
+                    let min = Point3::new(
+                        a.min.x().max(b.min.x()),
+                        a.min.y().max(b.min.y()),
+                        a.min.z().max(b.min.z()),
+                    );
+                    let mut max = Point3::new(
+                        a.max.x().min(b.max.x()),
+                        a.max.y().min(b.max.y()),
+                        a.max.z().min(b.max.z()),
+                    );
+                    // Disjoint operands leave an empty box
+                    for a in 0..3 {
+                        max.e[a] = max[a].max(min[a]);
+                    }
+                    Some(AABB::new(min, max))
+                }
+                (a, b) => a.or(b),
+            },
+            CsgOp::Difference => left,
+        }
+    }
+
+    fn crossings(&self, r: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord<'_>> {
+        // Whether the ray starts inside an operand is only known from the
+        // crossings before `t_min`
+        let left = self.left.crossings(r, f64::NEG_INFINITY, t_max);
+        let right = self.right.crossings(r, f64::NEG_INFINITY, t_max);
+        let mut in_left = left.first().is_some_and(|rec| !rec.front_face);
+        let mut in_right = right.first().is_some_and(|rec| !rec.front_face);
+        let mut inside = self.op.contains(in_left, in_right);
+
+        let mut res = vec![];
+        let mut left = left.into_iter().peekable();
+        let mut right = right.into_iter().peekable();
+        loop {
+            let from_left = match (left.peek(), right.peek()) {
+                (Some(a), Some(b)) => a.t <= b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let next = if from_left { left.next() } else { right.next() };
+            let mut rec = next.expect("peeked crossing");
+            if from_left {
+                in_left = rec.front_face;
+            } else {
+                in_right = rec.front_face;
+            }
+            // On a surface shared by both operands, wait for the other crossing
+            // before deciding whether the combined solid changes
+            let tolerance = COINCIDENT * rec.t.abs().max(1.);
+            let shared =
+                |other: Option<&HitRecord>| other.is_some_and(|other| other.t - rec.t <= tolerance);
+            if shared(left.peek()) || shared(right.peek()) {
+                continue;
+            }
+            let now_inside = self.op.contains(in_left, in_right);
+            if now_inside == inside {
+                continue;
+            }
+            inside = now_inside;
+            if rec.t >= t_min {
+                let mut outward = if rec.front_face {
+                    rec.get_normal()
+                } else {
+                    -rec.get_normal()
+                };
+                if !from_left && self.op == CsgOp::Difference {
+                    outward = -outward;
+                }
+                rec.front_face = now_inside;
+                rec.set_shading_normal(outward);
+                res.push(rec);
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::LinearBVH;
+    use crate::hittable::Sphere;
+    use crate::material::Lambertian;
+    use crate::shapes::Cylinder;
+    use crate::vec3::{color, point3, Vec3};
+
+    fn gray() -> Lambertian {
+        Lambertian::new(color(0.5, 0.5, 0.5))
+    }
+
+    fn ball(x: f64) -> BoxedHittable {
+        Box::new(Sphere::new(point3(x, 0., 0.), 1., gray()))
+    }
+
+    /// Unit sphere with a hole of radius 0.3 along y.
+    fn drilled() -> Csg {
+        let drill = Cylinder::new(point3(0., -2., 0.), point3(0., 2., 0.), 0.3, true, gray());
+        Csg::new(CsgOp::Difference, ball(0.), Box::new(drill))
+    }
+
+    #[test]
+    fn difference_carves_hole() {
+        let shape = drilled();
+        let r = Ray::new(point3(0., 5., 0.), Vec3::new(0., -1., 0.));
+        assert!(shape.hit(&r, 0.001, f64::INFINITY).is_none());
+        let r = Ray::new(point3(0.5, 5., 0.), Vec3::new(0., -1., 0.));
+        assert!(shape.hit(&r, 0.001, f64::INFINITY).is_some());
+
+        // Through the sphere, across the hole
+        let r = Ray::new(point3(5., 0., 0.), Vec3::new(-1., 0., 0.));
+        let crossings = shape.crossings(&r, 0.001, f64::INFINITY);
+        let ts: Vec<f64> = crossings.iter().map(|rec| rec.t).collect();
+        let expected = [4., 4.7, 5.3, 6.];
+        assert_eq!(ts.len(), 4);
+        for (t, expected) in ts.iter().zip(expected.iter()) {
+            assert!((t - expected).abs() < 1e-9);
+        }
+        let entering: Vec<bool> = crossings.iter().map(|rec| rec.front_face).collect();
+        assert_eq!(entering, vec![true, false, true, false]);
+        // Leaving into the hole, the surface faces the axis of the drill
+        let hole = &crossings[1];
+        assert!((hole.get_normal() - Vec3::new(1., 0., 0.)).length() < 1e-9);
+
+        // Starting inside the solid
+        let r = Ray::new(point3(0.6, 0., 0.), Vec3::new(-1., 0., 0.));
+        let rec = shape.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 0.3).abs() < 1e-9);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn union_and_intersection_merge_intervals() {
+        let r = Ray::new(point3(-5., 0., 0.), Vec3::new(1., 0., 0.));
+        let union = Csg::new(CsgOp::Union, ball(0.), ball(1.));
+        let ts: Vec<f64> = union
+            .crossings(&r, 0.001, f64::INFINITY)
+            .iter()
+            .map(|rec| rec.t)
+            .collect();
+        assert_eq!(ts, vec![4., 7.]);
+
+        let lens = Csg::new(CsgOp::Intersection, ball(0.), ball(1.));
+        let ts: Vec<f64> = lens
+            .crossings(&r, 0.001, f64::INFINITY)
+            .iter()
+            .map(|rec| rec.t)
+            .collect();
+        assert_eq!(ts, vec![5., 6.]);
+        let bbox = lens.bounding_box(0., 0.).unwrap();
+        assert_eq!((bbox.min.x(), bbox.max.x()), (0., 1.));
+
+        // Nodes nest: removing the drilled sphere from the union leaves the
+        // inside of the hole, then the part of the second ball beyond it
+        let nested = Csg::new(CsgOp::Difference, Box::new(union), Box::new(drilled()));
+        let ts: Vec<f64> = nested
+            .crossings(&r, 0.001, f64::INFINITY)
+            .iter()
+            .map(|rec| rec.t)
+            .collect();
+        assert_eq!(ts.len(), 4);
+        for (t, expected) in ts.iter().zip([4.7, 5.3, 6., 7.].iter()) {
+            assert!((t - expected).abs() < 1e-9);
+        }
+        let rec = nested.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn csg_nodes_go_in_bvh() {
+        let objects: Vec<BoxedHittable> = vec![Box::new(drilled()), ball(5.)];
+        let bvh = LinearBVH::new(objects, 0., 1.);
+        let r = Ray::new(point3(0., 5., 0.), Vec3::new(0., -1., 0.));
+        assert!(bvh.hit(&r, 0.001, f64::INFINITY).is_none());
+        let r = Ray::new(point3(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let rec = bvh.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-9);
+    }
+}
@@ -1,32 +1,45 @@
 use crate::aabb::{surrounding_box, AABB};
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::utils::random_double;
 use crate::vec3::{dot, Point3, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub type BoxedHittable = Box<dyn Hittable + Send + Sync>;
 
-pub struct HitRecord {
+/// Distance past a crossing, relative to its ray parameter, where the search
+/// for the next crossing resumes.
+const CROSSING_STEP: f64 = 1e-7;
+
+pub struct HitRecord<'a> {
     pub p: Point3,
     normal: Vec3,
     pub t: f64,
+    /// Surface coordinates of the hit point, for texture lookups
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
-    pub mat: Option<Box<dyn Material + Send + Sync>>,
+    pub mat: Option<&'a (dyn Material + Send + Sync)>,
 }
 
-impl HitRecord {
+impl<'a> HitRecord<'a> {
     pub fn new(
         r: &Ray,
         p: Vec3,
         normal: Vec3,
         t: f64,
-        mat: impl Material + Sync + Send + 'static,
-    ) -> HitRecord {
+        mat: &'a (dyn Material + Send + Sync),
+    ) -> HitRecord<'a> {
         let mut new_ht = HitRecord {
             p,
             t,
+            u: 0.,
+            v: 0.,
             normal: Vec3::new(0., 0., 0.),
             front_face: true,
-            mat: Some(Box::new(mat)),
+            mat: Some(mat),
         };
         new_ht.set_face_normal(r, normal);
         new_ht
@@ -38,19 +51,88 @@ impl HitRecord {
             false => -outward_normal,
         };
     }
+    /// Replace the normal used for shading (e.g. an interpolated vertex normal) while
+    /// keeping `front_face` from the geometric normal.
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        self.normal = match self.front_face {
+            true => outward_normal,
+            false => -outward_normal,
+        };
+    }
     pub fn get_normal(&self) -> Vec3 {
         self.normal
     }
 }
 
+/// A point sampled on the surface of a shape.
+#[derive(Copy, Clone, Debug)]
+pub struct SurfaceSample {
+    pub p: Point3,
+    /// Density of the direction towards `p`, with respect to solid angle.
+    pub pdf: f64,
+}
+
 pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB>;
+
+    /// Whether the object emits light and should be sampled directly.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// Sample a point on the object as seen from `origin`, where the object
+    /// is at `time`.
+    fn sample(&self, _origin: Point3, _time: f64) -> Option<SurfaceSample> {
+        None
+    }
+    /// Solid angle density of `sample` picking `direction` from `origin` at
+    /// `time`.
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3, _time: f64) -> f64 {
+        0.
+    }
+    /// Every point where `r` crosses the surface of the object within
+    /// `[t_min, t_max]`, in order. On closed objects, crossings alternate
+    /// between entering (`front_face`) and leaving the object, which gives the
+    /// intervals of the ray inside it. The default finds them one at a time
+    /// with `hit`.
+    fn crossings(&self, r: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord<'_>> {
+        let mut res = vec![];
+        let mut t_min = t_min;
+        while let Some(rec) = self.hit(r, t_min, t_max) {
+            t_min = rec.t + CROSSING_STEP * rec.t.abs().max(1.);
+            res.push(rec);
+        }
+        res
+    }
+}
+
+impl<H> Hittable for Arc<H>
+where
+    H: Hittable + ?Sized,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        (**self).hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        (**self).bounding_box(time0, time1)
+    }
+    fn is_emissive(&self) -> bool {
+        (**self).is_emissive()
+    }
+    fn sample(&self, origin: Point3, time: f64) -> Option<SurfaceSample> {
+        (**self).sample(origin, time)
+    }
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        (**self).pdf_value(origin, direction, time)
+    }
+    fn crossings(&self, r: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord<'_>> {
+        (**self).crossings(r, t_min, t_max)
+    }
 }
 
 pub struct Sphere<T>
 where
-    T: Material + Copy,
+    T: Material,
 {
     center: Point3,
     radius: f64,
@@ -59,7 +141,7 @@ where
 
 impl<T> Sphere<T>
 where
-    T: Material + Copy,
+    T: Material,
 {
     pub fn new(center: Vec3, radius: f64, material: T) -> Sphere<T> {
         Sphere {
@@ -72,35 +154,11 @@ where
 
 impl<T> Hittable for Sphere<T>
 where
-    T: 'static + Material + Send + Sync + Copy,
+    T: Material + Send + Sync,
 {
     #[inline]
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin() - self.center;
-        let a = r.direction().length_squared();
-        let half_b = dot(oc, r.direction());
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b.powi(2) - a * c;
-        if discriminant < 0. {
-            None
-        } else {
-            let sq_dis = discriminant.sqrt();
-            let mut root = (-half_b - sq_dis) / a;
-            let has_hit = if (root < t_min) | (root > t_max) {
-                root = (-half_b + sq_dis) / a;
-                !((root < t_min) | (root > t_max))
-            } else {
-                true
-            };
-            if has_hit {
-                let p = r.at(root);
-                let outward_normal = (p - self.center) / self.radius;
-                let rec = HitRecord::new(r, p, outward_normal, root, self.material);
-                Some(rec)
-            } else {
-                None
-            }
-        }
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &self.material, r, t_min, t_max)
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
@@ -109,6 +167,191 @@ where
             self.center + Vec3::new(self.radius, self.radius, self.radius),
         ))
     }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample(&self, origin: Point3, _time: f64) -> Option<SurfaceSample> {
+        sample_sphere(self.center, self.radius, origin)
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
+        match cos_theta_max(self.center, self.radius, origin) {
+            Some(cos_theta_max)
+                if self
+                    .hit(&Ray::new(origin, direction), 0.001, f64::INFINITY)
+                    .is_some() =>
+            {
+                1. / (2. * PI * (1. - cos_theta_max))
+            }
+            _ => 0.,
+        }
+    }
+}
+
+/// Sphere moving in a straight line from `center0` at `time0` to `center1` at
+/// `time1`, and resting at either end outside of that interval.
+pub struct MovingSphere<T>
+where
+    T: Material,
+{
+    center0: Point3,
+    center1: Point3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: T,
+}
+
+impl<T> MovingSphere<T>
+where
+    T: Material,
+{
+    pub fn new(
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: T,
+    ) -> MovingSphere<T> {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
+        }
+    }
+
+    fn center(&self, time: f64) -> Point3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).clamp(0., 1.);
+        self.center0 + (self.center1 - self.center0) * s
+    }
+}
+
+impl<T> Hittable for MovingSphere<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let center = self.center(r.time());
+        hit_sphere(center, self.radius, &self.material, r, t_min, t_max)
+    }
+
+    /// The motion is linear, so the boxes at both ends cover everything between.
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        let (c0, c1) = (self.center(time0), self.center(time1));
+        Some(surrounding_box(
+            &AABB::new(c0 - extent, c0 + extent),
+            &AABB::new(c1 - extent, c1 + extent),
+        ))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample(&self, origin: Point3, time: f64) -> Option<SurfaceSample> {
+        sample_sphere(self.center(time), self.radius, origin)
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let r = Ray::with_time(origin, direction, time);
+        match cos_theta_max(self.center(time), self.radius, origin) {
+            Some(cos_theta_max) if self.hit(&r, 0.001, f64::INFINITY).is_some() => {
+                1. / (2. * PI * (1. - cos_theta_max))
+            }
+            _ => 0.,
+        }
+    }
+}
+
+/// Cosine of the half-angle of the cone subtended by a sphere, or `None` when
+/// `origin` is inside it.
+fn cos_theta_max(center: Point3, radius: f64, origin: Point3) -> Option<f64> {
+    let dist_squared = (center - origin).length_squared();
+    let radius_squared = radius * radius;
+    if dist_squared <= radius_squared {
+        None
+    } else {
+        Some((1. - radius_squared / dist_squared).sqrt())
+    }
+}
+
+/// Uniformly sample the cone of directions subtended by a sphere.
+fn sample_sphere(center: Point3, radius: f64, origin: Point3) -> Option<SurfaceSample> {
+    let cos_theta_max = cos_theta_max(center, radius, origin)?;
+    let z = 1. + random_double() * (cos_theta_max - 1.);
+    let phi = 2. * PI * random_double();
+    let sin_theta = (1. - z * z).max(0.).sqrt();
+    let to_center = center - origin;
+    let direction = Onb::from_w(to_center).local(phi.cos() * sin_theta, phi.sin() * sin_theta, z);
+    // Closest intersection of the sampled direction with the sphere
+    let dist = to_center.length();
+    let half_chord = (radius * radius - dist * dist * (1. - z * z))
+        .max(0.)
+        .sqrt();
+    let p = origin + direction * (dist * z - half_chord);
+    Some(SurfaceSample {
+        p,
+        pdf: 1. / (2. * PI * (1. - cos_theta_max)),
+    })
+}
+
+/// Closest intersection of `r` with a sphere, within `[t_min, t_max]`.
+#[inline]
+fn hit_sphere<'a>(
+    center: Point3,
+    radius: f64,
+    material: &'a (dyn Material + Send + Sync),
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    let oc = r.origin() - center;
+    let a = r.direction().length_squared();
+    let half_b = dot(oc, r.direction());
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b.powi(2) - a * c;
+    if discriminant < 0. {
+        None
+    } else {
+        let sq_dis = discriminant.sqrt();
+        let mut root = (-half_b - sq_dis) / a;
+        let has_hit = if (root < t_min) | (root > t_max) {
+            root = (-half_b + sq_dis) / a;
+            !((root < t_min) | (root > t_max))
+        } else {
+            true
+        };
+        if has_hit {
+            let p = r.at(root);
+            let outward_normal = (p - center) / radius;
+            let mut rec = HitRecord::new(r, p, outward_normal, root, material);
+            let (u, v) = sphere_uv(outward_normal);
+            rec.u = u;
+            rec.v = v;
+            Some(rec)
+        } else {
+            None
+        }
+    }
+}
+
+/// Spherical coordinates of a point on the unit sphere, mapped to `[0, 1]`:
+/// `u` is the angle around the y axis starting from -x, `v` goes from the
+/// bottom (-y) to the top (+y).
+fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let theta = (-p.y()).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi / (2. * PI), theta / PI)
 }
 
 pub struct HittableList {
@@ -122,40 +365,126 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = None;
         let mut closest_so_far = t_max;
-        let temp_rec: Option<HitRecord> = None;
         for obj in self.objects.iter() {
-            if let Some(temp_rec) = (*obj).hit(r, t_min, closest_so_far) {
-                closest_so_far = temp_rec.t;
+            if let Some(rec) = obj.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some(rec);
             }
         }
-        temp_rec
+        closest
     }
 
+    /// Box around all the objects, or `None` if the list is empty or holds an
+    /// unbounded object.
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
-        if self.objects.is_empty() {
-            None
-        } else {
-            let mut first_box = true;
-            // Useless init since the first loop should go through setting res_box = val
-            let mut res_box = AABB::new(Point3::new(0., 0., 0.), Point3::new(0., 0., 0.));
-            let box_cond = self.objects.iter().any(|obj| {
-                obj.bounding_box(time0, time1).map_or(false, |val| {
-                    res_box = if first_box {
-                        val
-                    } else {
-                        surrounding_box(&res_box, &val)
-                    };
-                    first_box = false;
-                    true
-                })
-            });
-            if box_cond {
-                Some(res_box)
-            } else {
-                None
-            }
+        let mut boxes = self
+            .objects
+            .iter()
+            .map(|obj| obj.bounding_box(time0, time1));
+        let first = boxes.next()??;
+        boxes.try_fold(first, |res_box, bbox| {
+            Some(surrounding_box(&res_box, &bbox?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_uv_mapping() {
+        let close = |(u, v): (f64, f64), (eu, ev): (f64, f64)| {
+            (u - eu).abs() < 1e-12 && (v - ev).abs() < 1e-12
+        };
+        assert!(close(sphere_uv(Vec3::new(1., 0., 0.)), (0.5, 0.5)));
+        assert!(close(sphere_uv(Vec3::new(0., 0., 1.)), (0.25, 0.5)));
+        assert!(close(sphere_uv(Vec3::new(-1., 0., 0.)), (0., 0.5)));
+        assert_eq!(sphere_uv(Vec3::new(0., 1., 0.)).1, 1.);
+        assert_eq!(sphere_uv(Vec3::new(0., -1., 0.)).1, 0.);
+    }
+
+    #[test]
+    fn moving_sphere_follows_time() {
+        use crate::material::Lambertian;
+        use crate::vec3::{color, point3};
+
+        let sphere = MovingSphere::new(
+            point3(0., 0., 0.),
+            point3(0., 4., 0.),
+            0.,
+            1.,
+            1.,
+            Lambertian::new(color(0.5, 0.5, 0.5)),
+        );
+        let direction = Vec3::new(0., 0., -1.);
+        let at = |y: f64, time: f64| Ray::with_time(point3(0., y, 5.), direction, time);
+        assert!(sphere.hit(&at(0., 0.), 0.001, f64::INFINITY).is_some());
+        assert!(sphere.hit(&at(0., 1.), 0.001, f64::INFINITY).is_none());
+        assert!(sphere.hit(&at(2., 0.5), 0.001, f64::INFINITY).is_some());
+        // Resting after the end of the motion
+        assert!(sphere.hit(&at(4., 3.), 0.001, f64::INFINITY).is_some());
+
+        let bbox = sphere.bounding_box(0., 0.5).unwrap();
+        assert_eq!(bbox.min, point3(-1., -1., -1.));
+        assert_eq!(bbox.max, point3(1., 3., 1.));
+    }
+
+    #[test]
+    fn moving_light_is_sampled_where_it_is() {
+        use crate::material::DiffuseLight;
+        use crate::vec3::{color, point3};
+
+        let sphere = MovingSphere::new(
+            point3(0., 0., -5.),
+            point3(4., 0., -5.),
+            0.,
+            1.,
+            1.,
+            DiffuseLight::new(color(4., 4., 4.)),
+        );
+        assert!(sphere.is_emissive());
+        let origin = point3(0., 0., 0.);
+        for _ in 0..100 {
+            let sample = sphere.sample(origin, 0.75).unwrap();
+            assert!(((sample.p - point3(3., 0., -5.)).length() - 1.).abs() < 1e-9);
+            let pdf = sphere.pdf_value(origin, sample.p - origin, 0.75);
+            assert!((pdf - sample.pdf).abs() < 1e-9 * pdf);
         }
+        // The sphere has left the starting point by the end of the motion
+        let start = Vec3::new(0., 0., -1.);
+        assert!(sphere.pdf_value(origin, start, 0.) > 0.);
+        assert_eq!(sphere.pdf_value(origin, start, 1.), 0.);
+    }
+
+    #[test]
+    fn list_returns_closest_hit_and_joint_box() {
+        use crate::material::Lambertian;
+        use crate::shapes::Plane;
+        use crate::vec3::{color, point3};
+
+        let sphere = |z: f64| {
+            Box::new(Sphere::new(
+                point3(0., 0., z),
+                1.,
+                Lambertian::new(color(0.5, 0.5, 0.5)),
+            )) as BoxedHittable
+        };
+        let mut list = HittableList::new(vec![sphere(-4.), sphere(0.), sphere(4.)]);
+        let r = Ray::new(point3(0., 0., 10.), Vec3::new(0., 0., -1.));
+        assert_eq!(list.hit(&r, 0.001, f64::INFINITY).unwrap().t, 5.);
+        let bbox = list.bounding_box(0., 0.).unwrap();
+        assert_eq!((bbox.min.z(), bbox.max.z()), (-5., 5.));
+
+        list.objects.push(Box::new(Plane::new(
+            point3(0., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            Lambertian::new(color(0.5, 0.5, 0.5)),
+        )));
+        assert!(list.bounding_box(0., 0.).is_none());
+        assert!(HittableList::new(vec![]).bounding_box(0., 0.).is_none());
     }
 }
@@ -0,0 +1,209 @@
+use std::io::{self, Seek, Write};
+use std::path::Path;
+
+use exr::prelude::{Image, SpecificChannels, Vec2, WritableImage};
+
+use crate::vec3::{color, Color};
+
+/// Image file formats, chosen from the output file extension.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// ASCII PPM, 8-bit sRGB
+    Ppm,
+    /// 8-bit sRGB PNG
+    Png,
+    /// Linear 32-bit float OpenEXR
+    Exr,
+    /// Linear Radiance RGBE
+    Hdr,
+}
+
+impl ImageFormat {
+    pub const EXTENSIONS: [&'static str; 4] = ["ppm", "png", "exr", "hdr"];
+
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+/// Linear radiance of a rendered image, stored row by row from the top.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![color(0., 0., 0.); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, c: Color) {
+        self.pixels[y * self.width + x] = c;
+    }
+
+    /// Write the image in the given format. EXR needs to seek in its output.
+    pub fn write<W: Write + Seek>(&self, format: ImageFormat, writer: W) -> io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(writer),
+            ImageFormat::Png => self.write_png(writer),
+            ImageFormat::Exr => self.write_exr(writer),
+            ImageFormat::Hdr => self.write_hdr(writer),
+        }
+    }
+
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P3\n{} {}\n255\n", self.width, self.height)?;
+        for c in &self.pixels {
+            let [r, g, b] = to_srgb8(*c);
+            writeln!(writer, "{} {} {}", r, g, b)?;
+        }
+        Ok(())
+    }
+
+    fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+        let data: Vec<u8> = self.pixels.iter().flat_map(|c| to_srgb8(*c)).collect();
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&data)?;
+        png_writer.finish()?;
+        Ok(())
+    }
+
+    fn write_exr<W: Write + Seek>(&self, writer: W) -> io::Result<()> {
+        let channels = SpecificChannels::rgb(|pos: Vec2<usize>| {
+            let c = self.get(pos.x(), pos.y());
+            (c.x() as f32, c.y() as f32, c.z() as f32)
+        });
+        Image::from_channels((self.width, self.height), channels)
+            .write()
+            .to_buffered(writer)
+            .map_err(io::Error::other)
+    }
+
+    fn write_hdr<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        // Flat scanlines, which every reader accepts alongside run-length encoding
+        let data: Vec<u8> = self.pixels.iter().flat_map(|c| to_rgbe(*c)).collect();
+        writer.write_all(&data)
+    }
+}
+
+fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Inverse of the sRGB transfer function, for 8-bit inputs read from images.
+pub fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear color as 8-bit sRGB, clamping out-of-range and NaN values.
+pub fn to_srgb8(c: Color) -> [u8; 3] {
+    let encode = |x: f64| {
+        let x = if x.is_nan() { 0. } else { x.clamp(0., 1.) };
+        (255. * linear_to_srgb(x)).round() as u8
+    };
+    [encode(c.x()), encode(c.y()), encode(c.z())]
+}
+
+/// Shared-exponent encoding used by Radiance HDR files.
+fn to_rgbe(c: Color) -> [u8; 4] {
+    let (r, g, b) = (c.x().max(0.), c.y().max(0.), c.z().max(0.));
+    let max = r.max(g).max(b);
+    if max.is_nan() || max < 1e-32 {
+        return [0; 4];
+    }
+    // max = mantissa * 2^exp with mantissa in [0.5, 1)
+    let exp = max.log2().floor() as i32 + 1;
+    let scale = 256. / 2f64.powi(exp);
+    let byte = |x: f64| (x * scale).min(255.) as u8;
+    [byte(r), byte(g), byte(b), (exp + 128).clamp(0, 255) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(
+            ImageFormat::from_path(Path::new("out.PNG")),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a/b.exr")),
+            Some(ImageFormat::Exr)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("out.jpg")), None);
+        assert_eq!(ImageFormat::from_path(Path::new("out")), None);
+    }
+
+    #[test]
+    fn srgb_encoding_clamps() {
+        assert_eq!(to_srgb8(color(0., 1., 4.)), [0, 255, 255]);
+        assert_eq!(to_srgb8(color(-1., f64::NAN, 0.5)), [0, 0, 188]);
+        for x in [0.001, 0.3, 0.9] {
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn rgbe_round_trip() {
+        let c = color(3.5, 0.25, 1e-3);
+        let [r, g, b, e] = to_rgbe(c);
+        let scale = 2f64.powi(i32::from(e) - 128 - 8);
+        let decoded = [
+            f64::from(r) * scale,
+            f64::from(g) * scale,
+            f64::from(b) * scale,
+        ];
+        for (d, x) in decoded.iter().zip(c.e.iter()) {
+            assert!((d - x).abs() <= 4. / 256., "{} vs {}", d, x);
+        }
+        assert_eq!(to_rgbe(color(0., 0., 0.)), [0; 4]);
+    }
+
+    #[test]
+    fn writes_png_and_exr() {
+        let mut fb = Framebuffer::new(3, 2);
+        fb.set(2, 1, color(1., 0.5, 0.));
+        let mut png = Cursor::new(Vec::new());
+        fb.write(ImageFormat::Png, &mut png).unwrap();
+        assert_eq!(&png.get_ref()[1..4], b"PNG");
+        let mut exr = Cursor::new(Vec::new());
+        fb.write(ImageFormat::Exr, &mut exr).unwrap();
+        assert_eq!(&exr.get_ref()[..4], &[0x76, 0x2f, 0x31, 0x01]);
+    }
+}
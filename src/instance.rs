@@ -0,0 +1,421 @@
+use crate::aabb::{surrounding_box, AABB};
+use crate::hittable::{HitRecord, Hittable, SurfaceSample};
+use crate::mat4::Mat4;
+use crate::ray::Ray;
+use crate::vec3::{unit_vector, Point3, Vec3};
+use std::sync::Arc;
+
+/// Number of times sampled between keyframes to bound the motion of an instance.
+const MOTION_STEPS: usize = 16;
+
+/// Scale, rotation and translation of an instance at a given time, kept apart
+/// so that they can be interpolated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f64,
+    pub scale: Vec3,
+    /// Degrees around x, then y, then z
+    pub rotate: Vec3,
+    pub translate: Vec3,
+}
+
+const AXES: [Vec3; 3] = [
+    Vec3 { e: [1., 0., 0.] },
+    Vec3 { e: [0., 1., 0.] },
+    Vec3 { e: [0., 0., 1.] },
+];
+
+impl Keyframe {
+    /// Scale, then rotate, then translate.
+    pub fn matrix(&self) -> Mat4 {
+        let mut transform = Mat4::scaling(self.scale);
+        for (a, axis) in AXES.iter().enumerate() {
+            transform = Mat4::rotation(*axis, self.rotate[a]) * transform;
+        }
+        Mat4::translation(self.translate) * transform
+    }
+
+    /// Inverse of `matrix`, undoing each step in reverse order rather than
+    /// inverting a general matrix.
+    fn inverse_matrix(&self) -> Mat4 {
+        let mut inverse = Mat4::translation(-self.translate);
+        for (a, axis) in AXES.iter().enumerate().rev() {
+            inverse = Mat4::rotation(*axis, -self.rotate[a]) * inverse;
+        }
+        let s = self.scale;
+        Mat4::scaling(Vec3::new(1. / s.x(), 1. / s.y(), 1. / s.z())) * inverse
+    }
+
+    fn placement(&self) -> Placement {
+        let inverse = self.inverse_matrix();
+        Placement {
+            transform: self.matrix(),
+            inverse,
+            normal_transform: inverse.transpose(),
+        }
+    }
+
+    /// Bound on the acceleration of points within `radius` of the object's
+    /// origin, while moving from `self` to `next`. Translation is linear in
+    /// time, so only the rotation and its interplay with scaling bend paths.
+    fn max_acceleration(&self, next: &Keyframe, radius: f64) -> f64 {
+        let duration = next.time - self.time;
+        let turn = next.rotate - self.rotate;
+        let angular_speed =
+            (turn.x().abs() + turn.y().abs() + turn.z().abs()).to_radians() / duration;
+        let growth = next.scale - self.scale;
+        let growth_speed = growth.x().abs().max(growth.y().abs()).max(growth.z().abs()) / duration;
+        let max_scale = (0..3)
+            .map(|a| self.scale[a].abs().max(next.scale[a].abs()))
+            .fold(0., f64::max);
+        radius * angular_speed * (angular_speed * max_scale + 2. * growth_speed)
+    }
+
+    fn lerp(&self, other: &Keyframe, time: f64) -> Keyframe {
+        let s = (time - self.time) / (other.time - self.time);
+        let mix = |a: Vec3, b: Vec3| a + (b - a) * s;
+        Keyframe {
+            time,
+            scale: mix(self.scale, other.scale),
+            rotate: mix(self.rotate, other.rotate),
+            translate: mix(self.translate, other.translate),
+        }
+    }
+}
+
+/// A transform with the inverses needed to intersect rays.
+#[derive(Copy, Clone, Debug)]
+struct Placement {
+    transform: Mat4,
+    inverse: Mat4,
+    /// Inverse transpose, for normals
+    normal_transform: Mat4,
+}
+
+impl Placement {
+    /// Panics if `transform` cannot be inverted.
+    fn new(transform: Mat4) -> Placement {
+        let inverse = transform
+            .inverse()
+            .expect("Instance transform is not invertible");
+        Placement {
+            transform,
+            inverse,
+            normal_transform: inverse.transpose(),
+        }
+    }
+
+    fn bounding_box(&self, bbox: &AABB) -> AABB {
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for corner in 0..8 {
+            let pick = |a: usize| {
+                if corner & (1 << a) == 0 {
+                    bbox.min[a]
+                } else {
+                    bbox.max[a]
+                }
+            };
+            let p = self
+                .transform
+                .transform_point(Point3::new(pick(0), pick(1), pick(2)));
+            for a in 0..3 {
+                min.e[a] = min[a].min(p[a]);
+                max.e[a] = max[a].max(p[a]);
+            }
+        }
+        AABB::new(min, max)
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Motion {
+    Static(Placement),
+    /// Keyframes sorted by time, holding still before the first and after the
+    /// last, with the placement at each of them
+    Keyframed(Vec<Keyframe>, Vec<Placement>),
+}
+
+/// A shared object placed in the scene by an affine transform, which may be
+/// animated with keyframes.
+///
+/// Rays are brought into object space by the inverse transform, without
+/// renormalizing their direction so that hit distances stay the same in both
+/// spaces. Normals go back to world space by the inverse transpose.
+pub struct Instance {
+    object: Arc<dyn Hittable + Send + Sync>,
+    motion: Motion,
+}
+
+impl Instance {
+    /// Panics if `transform` cannot be inverted.
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, transform: Mat4) -> Instance {
+        Instance {
+            object,
+            motion: Motion::Static(Placement::new(transform)),
+        }
+    }
+
+    /// Instance moving through `keyframes`, interpolated linearly in time.
+    /// Panics if there are no keyframes or one cannot be inverted.
+    pub fn keyframed(
+        object: Arc<dyn Hittable + Send + Sync>,
+        mut keyframes: Vec<Keyframe>,
+    ) -> Instance {
+        assert!(!keyframes.is_empty(), "Instance has no keyframes");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        assert!(
+            keyframes.iter().all(|k| k.matrix().inverse().is_some()),
+            "Instance keyframe is not invertible"
+        );
+        let placements = keyframes.iter().map(Keyframe::placement).collect();
+        Instance {
+            object,
+            motion: Motion::Keyframed(keyframes, placements),
+        }
+    }
+
+    fn placement(&self, time: f64) -> Placement {
+        match &self.motion {
+            Motion::Static(placement) => *placement,
+            Motion::Keyframed(keyframes, placements) => {
+                let next = keyframes.partition_point(|k| k.time <= time);
+                if next == 0 {
+                    placements[0]
+                } else if next == keyframes.len() || keyframes[next - 1].time == time {
+                    placements[next - 1]
+                } else {
+                    keyframes[next - 1].lerp(&keyframes[next], time).placement()
+                }
+            }
+        }
+    }
+
+    /// Bound on the acceleration of points within `radius` of the object's
+    /// origin around `time`, zero outside of the keyframes.
+    fn max_acceleration(&self, time: f64, radius: f64) -> f64 {
+        match &self.motion {
+            Motion::Keyframed(keyframes, _) => {
+                let next = keyframes.partition_point(|k| k.time <= time);
+                if next == 0 || next == keyframes.len() {
+                    0.
+                } else {
+                    keyframes[next - 1].max_acceleration(&keyframes[next], radius)
+                }
+            }
+            Motion::Static(_) => 0.,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let placement = self.placement(r.time());
+        let local = Ray::with_time(
+            placement.inverse.transform_point(r.origin()),
+            placement.inverse.transform_vector(r.direction()),
+            r.time(),
+        );
+        let mut rec = self.object.hit(&local, t_min, t_max)?;
+        rec.p = placement.transform.transform_point(rec.p);
+        // The transform keeps the normal on the side the ray comes from, so
+        // `front_face` still holds
+        let local_outward = if rec.front_face {
+            rec.get_normal()
+        } else {
+            -rec.get_normal()
+        };
+        rec.set_shading_normal(unit_vector(
+            &placement.normal_transform.transform_vector(local_outward),
+        ));
+        Some(rec)
+    }
+
+    /// The box around the transformed corners of the object's box. Moving
+    /// instances take the union of the boxes at the keyframes and at regular
+    /// steps between them, each grown by how far paths can bulge out of the
+    /// chord between two steps, like the sagitta of an arc.
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        let bbox = self.object.bounding_box(time0, time1)?;
+        let keyframes = match &self.motion {
+            Motion::Static(placement) => return Some(placement.bounding_box(&bbox)),
+            Motion::Keyframed(keyframes, _) => keyframes,
+        };
+        let mut times = vec![time0, time1];
+        times.extend(
+            keyframes
+                .iter()
+                .map(|k| k.time)
+                .filter(|&t| t > time0 && t < time1),
+        );
+        times.sort_by(f64::total_cmp);
+        let radius = (0..3)
+            .map(|a| bbox.min[a].abs().max(bbox.max[a].abs()).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        let mut res = self.placement(time0).bounding_box(&bbox);
+        for pair in times.windows(2) {
+            let step = (pair[1] - pair[0]) / MOTION_STEPS as f64;
+            // A path strays from its chord by at most a step squared over 8
+            // times its acceleration
+            let acceleration = self.max_acceleration((pair[0] + pair[1]) / 2., radius);
+            let pad = step * step / 8. * acceleration;
+            for i in 0..=MOTION_STEPS {
+                let b = self
+                    .placement(pair[0] + step * i as f64)
+                    .bounding_box(&bbox);
+                res = surrounding_box(&res, &AABB::new(b.min - pad, b.max + pad));
+            }
+        }
+        Some(res)
+    }
+
+    /// Only transforms preserving angles keep the solid angle densities of
+    /// the object's light samples, so other instances, and moving ones, are
+    /// not sampled as lights (they still emit when hit).
+    fn is_emissive(&self) -> bool {
+        match &self.motion {
+            Motion::Static(placement) => {
+                placement.transform.is_similarity() && self.object.is_emissive()
+            }
+            Motion::Keyframed(..) => false,
+        }
+    }
+
+    fn sample(&self, origin: Point3, time: f64) -> Option<SurfaceSample> {
+        let placement = self.placement(time);
+        let sample = self
+            .object
+            .sample(placement.inverse.transform_point(origin), time)?;
+        Some(SurfaceSample {
+            p: placement.transform.transform_point(sample.p),
+            pdf: sample.pdf,
+        })
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let placement = self.placement(time);
+        self.object.pdf_value(
+            placement.inverse.transform_point(origin),
+            placement.inverse.transform_vector(direction),
+            time,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::material::Lambertian;
+    use crate::vec3::{color, dot, point3};
+
+    fn unit_sphere() -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere::new(
+            point3(0., 0., 0.),
+            1.,
+            Lambertian::new(color(0.5, 0.5, 0.5)),
+        ))
+    }
+
+    #[test]
+    fn hits_transformed_object() {
+        // An ellipsoid stretched along x, then moved up
+        let transform =
+            Mat4::translation(Vec3::new(0., 5., 0.)) * Mat4::scaling(Vec3::new(2., 1., 1.));
+        let instance = Instance::new(unit_sphere(), transform);
+
+        let r = Ray::new(point3(-10., 5., 0.), Vec3::new(1., 0., 0.));
+        let rec = instance.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 8.).abs() < 1e-9);
+        assert!((rec.p - point3(-2., 5., 0.)).length() < 1e-9);
+        assert!((rec.get_normal() - Vec3::new(-1., 0., 0.)).length() < 1e-9);
+
+        // Normals follow the inverse transpose, not the transform itself
+        let r = Ray::new(
+            point3(0., 15., 0.),
+            point3(1., 5.5, 0.) - point3(0., 15., 0.),
+        );
+        let rec = instance.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let local = point3(rec.p.x() / 2., rec.p.y() - 5., rec.p.z());
+        let expected = unit_vector(&Vec3::new(local.x() / 2., local.y(), local.z()));
+        assert!((rec.get_normal() - expected).length() < 1e-9);
+        assert!(dot(rec.get_normal(), r.direction()) < 0.);
+
+        let r = Ray::new(point3(-10., 6.5, 0.), Vec3::new(1., 0., 0.));
+        assert!(instance.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn bounding_box_covers_rotated_object() {
+        let transform =
+            Mat4::rotation(Vec3::new(0., 0., 1.), 45.) * Mat4::scaling(Vec3::new(2., 1., 1.));
+        let bbox = Instance::new(unit_sphere(), transform)
+            .bounding_box(0., 0.)
+            .unwrap();
+        // Corners of the [-2, 2] x [-1, 1] box, rotated by 45 degrees
+        let extent = 3. / 2f64.sqrt();
+        assert!((bbox.max - point3(extent, extent, 1.)).length() < 1e-9);
+        assert!((bbox.min + point3(extent, extent, 1.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn keyframes_move_instance() {
+        let keyframe = |time: f64, x: f64, angle: f64| Keyframe {
+            time,
+            scale: Vec3::new(1., 1., 1.),
+            rotate: Vec3::new(0., 0., angle),
+            translate: Vec3::new(x, 0., 0.),
+        };
+        let instance = Instance::keyframed(
+            unit_sphere(),
+            vec![keyframe(1., 4., 90.), keyframe(0., 0., 0.)],
+        );
+        let down =
+            |x: f64, time: f64| Ray::with_time(point3(x, 5., 0.), Vec3::new(0., -1., 0.), time);
+        assert!(instance.hit(&down(0., 0.), 0.001, f64::INFINITY).is_some());
+        assert!(instance.hit(&down(2., 0.5), 0.001, f64::INFINITY).is_some());
+        assert!(instance.hit(&down(0., 0.5), 0.001, f64::INFINITY).is_none());
+        assert!(instance.hit(&down(4., 2.), 0.001, f64::INFINITY).is_some());
+
+        // Covers both ends, and the corners of the object's box sticking out
+        // halfway through the rotation
+        let bbox = instance.bounding_box(0., 1.).unwrap();
+        assert!(bbox.min.x() <= -1. && bbox.max.x() >= 5.);
+        assert!(bbox.max.y() >= 2f64.sqrt() && bbox.max.y() < 2f64.sqrt() + 0.01);
+        assert!(bbox.min.z() <= -1. && bbox.max.z() >= 1. && bbox.max.z() < 1.01);
+        // Moving instances are never sampled as lights
+        assert!(!instance.is_emissive());
+    }
+
+    #[test]
+    fn moving_bounding_box_contains_whole_path() {
+        let keyframe = |time: f64, scale: f64, angle: f64| Keyframe {
+            time,
+            scale: Vec3::new(scale, 1., 1.),
+            rotate: Vec3::new(angle / 3., angle / 2., angle),
+            translate: Vec3::new(0., 0., 0.),
+        };
+        let instance = Instance::keyframed(
+            unit_sphere(),
+            vec![keyframe(0., 1., 0.), keyframe(1., 2., 170.)],
+        );
+        let bbox = instance.bounding_box(0., 1.).unwrap();
+        let object_box = unit_sphere().bounding_box(0., 1.).unwrap();
+        for i in 0..=1000 {
+            let at = instance
+                .placement(f64::from(i) / 1000.)
+                .bounding_box(&object_box);
+            for a in 0..3 {
+                assert!(bbox.min[a] <= at.min[a] && at.max[a] <= bbox.max[a]);
+            }
+        }
+        // Interpolated placements are inverted without a general inverse
+        let placement = instance.placement(0.4);
+        let p = point3(0.3, -0.7, 1.2);
+        let back = placement
+            .inverse
+            .transform_point(placement.transform.transform_point(p));
+        assert!((back - p).length() < 1e-9);
+    }
+}
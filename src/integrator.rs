@@ -0,0 +1,194 @@
+use crate::background::Background;
+use crate::hittable::{HitRecord, Hittable};
+use crate::light::Lights;
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::{color, Color};
+
+/// Bounces always traced before Russian roulette may terminate a path.
+const MIN_BOUNCES: i32 = 3;
+
+/// Tolerance on the ray parameter when checking that a shadow ray reached the
+/// sampled light point.
+const SHADOW_EPSILON: f64 = 1e-4;
+
+/// Estimate the radiance arriving along `r` by tracing a single path.
+///
+/// The path is extended iteratively while tracking its throughput, so deep
+/// bounce counts do not grow the stack. After `MIN_BOUNCES`, paths are
+/// terminated with Russian roulette (survivors are reweighted, which keeps the
+/// estimate unbiased); `max_depth` remains as a hard limit.
+///
+/// At non-specular vertices, emission is estimated both by sampling a point on
+/// one of the `lights` and by following the BSDF sample, and the two are
+/// combined with multiple importance sampling (power heuristic).
+pub fn ray_color(
+    r: &Ray,
+    world: &dyn Hittable,
+    lights: &Lights,
+    background: &Background,
+    max_depth: i32,
+) -> Color {
+    let mut radiance = color(0., 0., 0.);
+    let mut throughput = color(1., 1., 1.);
+    let mut ray = *r;
+    // Density of the BSDF sample that produced `ray`, `None` if it was not
+    // importance sampled against the lights (camera rays, specular bounces).
+    let mut bsdf_pdf: Option<f64> = None;
+    for depth in 0..max_depth {
+        let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => {
+                radiance += throughput * background.color(&ray);
+                break;
+            }
+        };
+        let mat = rec.mat.expect("Hit recorded with no material.");
+        let emitted = mat.emit(rec.u, rec.v, rec.p);
+        if emitted.max_component() > 0. {
+            let weight = bsdf_pdf.map_or(1., |pdf| {
+                power_heuristic(pdf, lights.pdf_value(&ray, rec.t))
+            });
+            radiance += throughput * emitted * weight;
+        }
+
+        if !mat.is_specular() && !lights.is_empty() {
+            radiance += throughput * direct_light(&ray, &rec, world, lights);
+        }
+
+        let (attenuation, scattered) = match mat.scatter(&ray, &rec) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+        bsdf_pdf = if mat.is_specular() {
+            None
+        } else {
+            Some(mat.pdf(&ray, &rec, scattered.direction()))
+        };
+        throughput = throughput * attenuation;
+        ray = scattered;
+
+        if depth >= MIN_BOUNCES {
+            let survival = throughput.max_component().min(1.);
+            if random_double() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+    radiance
+}
+
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0. {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
+/// Light reflected at `rec` from a single light sample, weighted against the
+/// BSDF sampling strategy.
+fn direct_light(r_in: &Ray, rec: &HitRecord, world: &dyn Hittable, lights: &Lights) -> Color {
+    let black = color(0., 0., 0.);
+    let sample = match lights.sample(rec.p, r_in.time()) {
+        Some(sample) if sample.pdf > 0. => sample,
+        _ => return black,
+    };
+    // The shadow ray reaches the sampled point at t = 1
+    let shadow_ray = Ray::with_time(rec.p, sample.p - rec.p, r_in.time());
+    match world.hit(&shadow_ray, 0.001, 1. + SHADOW_EPSILON) {
+        Some(light_rec) if light_rec.t >= 1. - SHADOW_EPSILON => {
+            let mat = rec.mat.expect("Hit recorded with no material.");
+            let emitted = light_rec.mat.expect("Hit recorded with no material.").emit(
+                light_rec.u,
+                light_rec.v,
+                light_rec.p,
+            );
+            let direction = shadow_ray.direction();
+            let weight = power_heuristic(sample.pdf, mat.pdf(r_in, rec, direction));
+            mat.eval(r_in, rec, direction) * emitted * (weight / sample.pdf)
+        }
+        _ => black,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{BoxedHittable, HitRecord, HittableList, Sphere};
+    use crate::material::{DiffuseLight, Lambertian, Material};
+    use crate::shapes::Plane;
+    use crate::vec3::{Point3, Vec3};
+
+    /// Half-reflective surface that also emits, so the exact radiance inside
+    /// a closed sphere is the geometric series 1 + 0.5 + 0.25 + ... = 2.
+    struct Furnace(Lambertian);
+
+    impl Material for Furnace {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+            self.0.scatter(r_in, rec)
+        }
+        fn emit(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+            color(1., 1., 1.)
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let furnace = Furnace(Lambertian::new(color(0.5, 0.5, 0.5)));
+        let world = Sphere::new(Point3::new(0., 0., 0.), 1., furnace);
+        let background = Background::None;
+        let no_lights = Lights::extract(&mut vec![]);
+        let n = 20000;
+        let mut total = 0.;
+        for _ in 0..n {
+            let r = Ray::new(Point3::new(0., 0., 0.), Vec3::random_range(-1., 1.));
+            total += ray_color(&r, &world, &no_lights, &background, 10000).x();
+        }
+        let mean = total / f64::from(n);
+        assert!((mean - 2.).abs() < 0.05, "mean radiance {}", mean);
+    }
+
+    #[test]
+    fn light_sampling_is_unbiased() {
+        // A plane lit by two spheres on the same axis, the small one hiding
+        // part of the large one from the plane
+        let scene = || -> Vec<BoxedHittable> {
+            let light = DiffuseLight::new(color(4., 4., 4.));
+            vec![
+                Box::new(Plane::new(
+                    Point3::new(0., 0., 0.),
+                    Vec3::new(0., 1., 0.),
+                    Lambertian::new(color(0.5, 0.5, 0.5)),
+                )),
+                Box::new(Sphere::new(Point3::new(0., 1.5, 0.), 1., light.clone())),
+                Box::new(Sphere::new(Point3::new(0., 5., 0.), 2.5, light)),
+            ]
+        };
+        let mut objects = scene();
+        let lights = Lights::extract(&mut objects);
+        let world = HittableList::new(objects);
+        let unsampled = HittableList::new(scene());
+        let no_lights = Lights::extract(&mut vec![]);
+
+        let eye = Point3::new(0., 1., 4.);
+        let r = Ray::new(eye, Point3::new(0.5, 0., 0.) - eye);
+        let n = 200000;
+        let mean = |world: &HittableList, lights: &Lights| {
+            let total: f64 = (0..n)
+                .map(|_| ray_color(&r, world, lights, &Background::None, 5).x())
+                .sum();
+            total / f64::from(n)
+        };
+        let sampled = mean(&world, &lights);
+        let reference = mean(&unsampled, &no_lights);
+        assert!(
+            (sampled - reference).abs() < 0.03 * reference,
+            "{} with light sampling, {} without",
+            sampled,
+            reference
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::hittable::{BoxedHittable, Hittable, SurfaceSample};
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::Point3;
+use std::sync::Arc;
+
+/// Relative tolerance on the ray parameter when matching the hit of a light
+/// with the one found in the world.
+const HIT_TOLERANCE: f64 = 1e-6;
+
+/// The emissive objects of a scene, sampled directly by the integrator.
+pub struct Lights {
+    lights: Vec<Arc<dyn Hittable + Send + Sync>>,
+}
+
+impl Lights {
+    /// Collect the emissive objects of `objects`. They stay in the list (so they
+    /// are still found by regular rays) but become shared with the light list.
+    pub fn extract(objects: &mut Vec<BoxedHittable>) -> Lights {
+        let mut lights = vec![];
+        for obj in std::mem::take(objects) {
+            if obj.is_emissive() {
+                let light: Arc<dyn Hittable + Send + Sync> = Arc::from(obj);
+                objects.push(Box::new(Arc::clone(&light)));
+                lights.push(light);
+            } else {
+                objects.push(obj);
+            }
+        }
+        Lights { lights }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Pick a light uniformly and sample a point on it at `time`. The pdf
+    /// accounts for the light selection.
+    pub fn sample(&self, origin: Point3, time: f64) -> Option<SurfaceSample> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let n = self.lights.len();
+        let idx = ((random_double() * n as f64) as usize).min(n - 1);
+        self.lights[idx]
+            .sample(origin, time)
+            .map(|s| SurfaceSample {
+                pdf: s.pdf / n as f64,
+                ..s
+            })
+    }
+
+    /// Pdf of `sample` picking the direction of `r`, which first meets an
+    /// emitter at `t`. Lights further along are hidden behind it: their
+    /// samples in that direction are always shadowed, so they do not count.
+    pub fn pdf_value(&self, r: &Ray, t: f64) -> f64 {
+        if self.lights.is_empty() {
+            return 0.;
+        }
+        let reach = t * (1. + HIT_TOLERANCE);
+        let total: f64 = self
+            .lights
+            .iter()
+            .filter(|light| light.hit(r, 0.001, reach).is_some())
+            .map(|light| light.pdf_value(r.origin(), r.direction(), r.time()))
+            .sum();
+        total / self.lights.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::material::DiffuseLight;
+    use crate::triangle::Triangle;
+    use crate::vec3::{color, point3, Vec3};
+
+    fn two_lights() -> Lights {
+        let light = DiffuseLight::new(color(4., 4., 4.));
+        let mut objects: Vec<BoxedHittable> = vec![
+            Box::new(Sphere::new(point3(0., 0., -5.), 1., light.clone())),
+            Box::new(Triangle::new(
+                point3(3., -1., -4.),
+                point3(5., -1., -4.),
+                point3(4., 1., -4.),
+                light,
+            )),
+        ];
+        let lights = Lights::extract(&mut objects);
+        assert_eq!(objects.len(), 2);
+        lights
+    }
+
+    #[test]
+    fn sample_pdf_matches_pdf_value() {
+        let lights = two_lights();
+        let origin = point3(0., 0., 0.);
+        for _ in 0..100 {
+            let sample = lights.sample(origin, 0.).unwrap();
+            // The shadow ray reaches the sampled point at t = 1
+            let pdf = lights.pdf_value(&Ray::new(origin, sample.p - origin), 1.);
+            assert!(
+                (pdf - sample.pdf).abs() < 1e-6 * pdf,
+                "{} {}",
+                pdf,
+                sample.pdf
+            );
+        }
+        let away = Ray::new(origin, Vec3::new(0., 0., 1.));
+        assert_eq!(lights.pdf_value(&away, f64::INFINITY), 0.);
+    }
+
+    #[test]
+    fn hidden_lights_do_not_count() {
+        let light = DiffuseLight::new(color(4., 4., 4.));
+        let front = Sphere::new(point3(0., 0., -3.), 1., light.clone());
+        let back = Sphere::new(point3(0., 0., -8.), 4., light.clone());
+        let origin = point3(0., 0., 0.);
+        let (front_pdf, back_pdf) = (
+            front.pdf_value(origin, Vec3::new(0., 0., -1.), 0.),
+            back.pdf_value(origin, Vec3::new(0., 0., -1.), 0.),
+        );
+        let mut objects: Vec<BoxedHittable> = vec![Box::new(front), Box::new(back)];
+        let lights = Lights::extract(&mut objects);
+
+        // Straight ahead, the front sphere hides the back one
+        let ahead = Ray::new(origin, Vec3::new(0., 0., -1.));
+        assert!((lights.pdf_value(&ahead, 2.) - front_pdf / 2.).abs() < 1e-12);
+        // Past the edge of the front sphere, only the back one is seen
+        let past = Ray::new(origin, Vec3::new(0., 0.45, -1.));
+        let t = objects[1].hit(&past, 0.001, f64::INFINITY).unwrap().t;
+        let pdf = lights.pdf_value(&past, t);
+        assert!((pdf - back_pdf / 2.).abs() < 1e-12, "{} {}", pdf, back_pdf);
+    }
+
+    #[test]
+    fn sphere_pdf_integrates_to_solid_angle() {
+        // E[1 / pdf] over samples is the solid angle subtended by the sphere
+        let sphere = Sphere::new(
+            point3(0., 0., -2.),
+            1.,
+            DiffuseLight::new(color(1., 1., 1.)),
+        );
+        let origin = point3(0., 0., 0.);
+        let n = 1000;
+        let total: f64 = (0..n)
+            .map(|_| 1. / sphere.sample(origin, 0.).unwrap().pdf)
+            .sum();
+        let expected = 2. * std::f64::consts::PI * (1. - (3_f64).sqrt() / 2.);
+        assert!((total / f64::from(n) - expected).abs() < 1e-9);
+        assert!(sphere.sample(point3(0., 0., -2.), 0.).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::vec3::{dot, unit_vector, Point3, Vec3};
+use std::ops;
+
+/// Affine transform as a 4x4 matrix, applied to column vectors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        m: [
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ],
+    };
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut res = Mat4::IDENTITY;
+        for a in 0..3 {
+            res.m[a][3] = offset[a];
+        }
+        res
+    }
+
+    pub fn scaling(factors: Vec3) -> Mat4 {
+        let mut res = Mat4::IDENTITY;
+        for a in 0..3 {
+            res.m[a][a] = factors[a];
+        }
+        res
+    }
+
+    /// Counterclockwise rotation of `degrees` around `axis`, looking down the axis.
+    pub fn rotation(axis: Vec3, degrees: f64) -> Mat4 {
+        let a = unit_vector(&axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let t = 1. - cos;
+        Mat4 {
+            m: [
+                [
+                    t * x * x + cos,
+                    t * x * y - sin * z,
+                    t * x * z + sin * y,
+                    0.,
+                ],
+                [
+                    t * x * y + sin * z,
+                    t * y * y + cos,
+                    t * y * z - sin * x,
+                    0.,
+                ],
+                [
+                    t * x * z - sin * y,
+                    t * y * z + sin * x,
+                    t * z * z + cos,
+                    0.,
+                ],
+                [0., 0., 0., 1.],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut res = Mat4::IDENTITY;
+        for (i, row) in self.m.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                res.m[j][i] = value;
+            }
+        }
+        res
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::IDENTITY.m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = 1. / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[col][k];
+                        inv[row][k] -= factor * inv[col][k];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        (self.transform_vector(p) + Vec3::new(m[0][3], m[1][3], m[2][3])) / w
+    }
+
+    /// Transform a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    /// Whether the transform only rotates, translates and scales uniformly, so
+    /// that it preserves angles.
+    pub fn is_similarity(&self) -> bool {
+        let columns = [0, 1, 2].map(|j| Vec3::new(self.m[0][j], self.m[1][j], self.m[2][j]));
+        let scale = columns[0].length_squared();
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * scale;
+        let projective = self.m[3] != [0., 0., 0., 1.];
+        !projective
+            && (0..3).all(|i| {
+                (0..3).all(|j| {
+                    let expected = if i == j { scale } else { 0. };
+                    close(dot(columns[i], columns[j]), expected)
+                })
+            })
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut res = [[0.; 4]; 4];
+        for (i, row) in res.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m: res }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::point3;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn transforms_points_and_vectors() {
+        let m = Mat4::translation(Vec3::new(1., 2., 3.))
+            * Mat4::rotation(Vec3::new(0., 0., 1.), 90.)
+            * Mat4::scaling(Vec3::new(2., 2., 2.));
+        assert!(close(
+            m.transform_point(point3(1., 0., 0.)),
+            point3(1., 4., 3.)
+        ));
+        assert!(close(
+            m.transform_vector(Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 2., 0.)
+        ));
+        assert!(m.is_similarity());
+        assert!(!Mat4::scaling(Vec3::new(1., 2., 1.)).is_similarity());
+    }
+
+    #[test]
+    fn inverse_undoes_transform() {
+        let m = Mat4::translation(Vec3::new(-3., 0.5, 2.))
+            * Mat4::rotation(Vec3::new(1., 1., 0.), 30.)
+            * Mat4::scaling(Vec3::new(1., 3., 0.5));
+        let inv = m.inverse().unwrap();
+        let p = point3(0.3, -1.2, 4.);
+        assert!(close(inv.transform_point(m.transform_point(p)), p));
+        let id = m * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((id.m[i][j] - Mat4::IDENTITY.m[i][j]).abs() < 1e-12);
+            }
+        }
+        assert!(Mat4::scaling(Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+}
@@ -1,70 +1,162 @@
 use crate::hittable::HitRecord;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::texture::{SharedTexture, SolidColor};
 use crate::utils::random_double;
 use crate::vec3::{
-    color, dot, random_in_unit_sphere, random_unit_vector, reflect, refract, unit_vector, Color,
-    Point3,
+    color, dot, random_unit_vector, reflect, refract, unit_vector, Color, Point3, Vec3,
 };
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub trait Material {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
     fn emit(&self, _u: f64, _v: f64, _p: Point3) -> Color {
         color(0., 0., 0.)
     }
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// Specular materials cannot be evaluated for an arbitrary direction, so
+    /// lights are never sampled directly from them.
+    fn is_specular(&self) -> bool {
+        true
+    }
+    /// BSDF times the cosine term, for light arriving from `direction`.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Color {
+        color(0., 0., 0.)
+    }
+    /// Solid angle density of `scatter` choosing `direction`.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> f64 {
+        0.
+    }
 }
 
-#[derive(Copy, Clone)]
+impl<M> Material for Arc<M>
+where
+    M: Material + ?Sized,
+{
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        (**self).scatter(r_in, rec)
+    }
+    fn emit(&self, u: f64, v: f64, p: Point3) -> Color {
+        (**self).emit(u, v, p)
+    }
+    fn is_emissive(&self) -> bool {
+        (**self).is_emissive()
+    }
+    fn is_specular(&self) -> bool {
+        (**self).is_specular()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        (**self).eval(r_in, rec, direction)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        (**self).pdf(r_in, rec, direction)
+    }
+}
+
+#[derive(Clone)]
 pub struct Lambertian {
-    color: Color,
+    albedo: SharedTexture,
 }
 
 impl Lambertian {
     pub fn new(col: Color) -> Lambertian {
-        Lambertian { color: col }
+        Lambertian::textured(Arc::new(SolidColor::new(col)))
+    }
+
+    pub fn textured(albedo: SharedTexture) -> Lambertian {
+        Lambertian { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let mut scatter_direction = rec.get_normal() + random_unit_vector();
         if scatter_direction.near_zero() {
             scatter_direction = rec.get_normal();
         }
-        let scattered = Ray::new(rec.p, scatter_direction);
-        let attenuation = self.color;
+        let scattered = Ray::with_time(rec.p, scatter_direction, r_in.time());
+        let attenuation = self.albedo.value(rec.u, rec.v, rec.p);
         Some((attenuation, scattered))
     }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * self.pdf(r_in, rec, direction)
+    }
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        dot(rec.get_normal(), unit_vector(&direction)).max(0.) / PI
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Metal {
-    color: Color,
+    albedo: SharedTexture,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(col: Color, f: f64) -> Metal {
+        Metal::textured(Arc::new(SolidColor::new(col)), f)
+    }
+
+    pub fn textured(albedo: SharedTexture, f: f64) -> Metal {
         Metal {
-            color: col,
+            albedo,
             fuzz: match f < 1. {
                 true => f,
                 false => 1.,
             },
         }
     }
+
+    /// Exponent of the Phong lobe around the mirror direction. Its spread
+    /// roughly matches perturbing the reflection by `fuzz` times a random
+    /// vector in the unit sphere.
+    fn exponent(&self) -> f64 {
+        2. / (self.fuzz * self.fuzz) - 2.
+    }
 }
 
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let reflected = reflect(unit_vector(&r_in.direction()), rec.get_normal());
-        let scattered = Ray::new(rec.p, reflected + random_in_unit_sphere() * self.fuzz);
-        let attenuation = self.color;
+        let direction = if self.is_specular() {
+            reflected
+        } else {
+            let cos_alpha = random_double().powf(1. / (self.exponent() + 1.));
+            let sin_alpha = (1. - cos_alpha * cos_alpha).sqrt();
+            let phi = 2. * PI * random_double();
+            Onb::from_w(reflected).local(phi.cos() * sin_alpha, phi.sin() * sin_alpha, cos_alpha)
+        };
+        let scattered = Ray::with_time(rec.p, direction, r_in.time());
+        let attenuation = self.albedo.value(rec.u, rec.v, rec.p);
         if dot(scattered.direction(), rec.get_normal()) > 0. {
             Some((attenuation, scattered))
         } else {
             None
         }
     }
+    fn is_specular(&self) -> bool {
+        self.fuzz <= 0.
+    }
+    /// Scaled so that the weight `eval / pdf` of sampled directions is the albedo.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        if dot(direction, rec.get_normal()) > 0. {
+            self.albedo.value(rec.u, rec.v, rec.p) * self.pdf(r_in, rec, direction)
+        } else {
+            color(0., 0., 0.)
+        }
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let reflected = reflect(unit_vector(&r_in.direction()), rec.get_normal());
+        let cos_alpha = dot(reflected, unit_vector(&direction)).max(0.);
+        let n = self.exponent();
+        (n + 1.) / (2. * PI) * cos_alpha.powf(n)
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -96,7 +188,7 @@ impl Material for Dielectric {
         } else {
             refract(unit_direction, rec.get_normal(), refraction_ratio)
         };
-        let scattered = Ray::new(rec.p, direction);
+        let scattered = Ray::with_time(rec.p, direction, r_in.time());
         Some((attenuation, scattered))
     }
 }
@@ -106,3 +198,147 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     r0 = r0 * r0;
     r0 + (1. - r0) * (1. - cosine).powf(5.)
 }
+
+#[derive(Clone)]
+pub struct DiffuseLight {
+    emit: SharedTexture,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight::textured(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn textured(emit: SharedTexture) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+        None
+    }
+    fn emit(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.emit.value(u, v, p)
+    }
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Phase function of a participating medium, scattering equally in all directions.
+#[derive(Clone)]
+pub struct Isotropic {
+    albedo: SharedTexture,
+}
+
+impl Isotropic {
+    pub fn new(col: Color) -> Isotropic {
+        Isotropic::textured(Arc::new(SolidColor::new(col)))
+    }
+
+    pub fn textured(albedo: SharedTexture) -> Isotropic {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let scattered = Ray::with_time(rec.p, random_unit_vector(), r_in.time());
+        Some((self.albedo.value(rec.u, rec.v, rec.p), scattered))
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * self.pdf(r_in, rec, direction)
+    }
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> f64 {
+        1. / (4. * PI)
+    }
+}
+
+/// Hit at the origin of a surface facing `outward`, for material tests.
+#[cfg(test)]
+pub(crate) fn record<'a>(
+    r: &Ray,
+    outward: Vec3,
+    mat: &'a (dyn Material + Send + Sync),
+) -> HitRecord<'a> {
+    HitRecord::new(r, Point3::new(0., 0., 0.), outward, 1., mat)
+}
+
+/// Checks that the weight `scatter` returns is `eval / pdf` of the direction
+/// it picked, which the integrator relies on to mix both strategies.
+#[cfg(test)]
+pub(crate) fn assert_scatter_matches_eval(
+    mat: &(dyn Material + Send + Sync),
+    r_in: &Ray,
+    outward: Vec3,
+) {
+    let rec = record(r_in, outward, mat);
+    for _ in 0..200 {
+        if let Some((attenuation, scattered)) = mat.scatter(r_in, &rec) {
+            let dir = scattered.direction();
+            let weight = mat.eval(r_in, &rec, dir) / mat.pdf(r_in, &rec, dir);
+            assert!((weight - attenuation).length() < 1e-6 * weight.length());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::point3;
+
+    #[test]
+    fn scatter_weight_matches_eval_over_pdf() {
+        let r_in = Ray::new(point3(-1., 1., 0.), Vec3::new(1., -1., 0.));
+        let materials: [Box<dyn Material + Send + Sync>; 3] = [
+            Box::new(Lambertian::new(color(0.5, 0.4, 0.3))),
+            Box::new(Metal::new(color(0.9, 0.8, 0.7), 0.3)),
+            Box::new(Isotropic::new(color(0.8, 0.8, 0.9))),
+        ];
+        for mat in materials.iter() {
+            assert_scatter_matches_eval(mat.as_ref(), &r_in, Vec3::new(0., 1., 0.));
+        }
+    }
+
+    #[test]
+    fn metal_pdf_integrates_to_one() {
+        // Uniform hemisphere sampling around the reflected direction, which is
+        // the normal here, so the whole lobe is above the surface.
+        let r_in = Ray::new(point3(0., 1., 0.), Vec3::new(0., -1., 0.));
+        let mat = Metal::new(color(1., 1., 1.), 0.5);
+        let rec = record(&r_in, Vec3::new(0., 1., 0.), &mat);
+        let n = 200000;
+        let mut total = 0.;
+        for _ in 0..n {
+            let mut d = random_unit_vector();
+            if d.y() < 0. {
+                d = -d;
+            }
+            total += mat.pdf(&r_in, &rec, d) * 2. * PI;
+        }
+        assert!((total / f64::from(n) - 1.).abs() < 0.02);
+    }
+
+    #[test]
+    fn diffuse_light_emits_texture_and_never_scatters() {
+        use crate::texture::CheckerTexture;
+
+        let checker = CheckerTexture::new(
+            Arc::new(SolidColor::new(color(4., 4., 4.))),
+            Arc::new(SolidColor::new(color(1., 0., 0.))),
+            1.,
+        );
+        let light = DiffuseLight::textured(Arc::new(checker));
+        assert!(light.is_emissive());
+        assert_eq!(light.emit(0., 0., point3(0.5, 0.5, 0.5)), color(1., 0., 0.));
+        assert_eq!(light.emit(0., 0., point3(1.5, 0.5, 0.5)), color(4., 4., 4.));
+
+        let r_in = Ray::new(point3(0., 1., 0.), Vec3::new(0., -1., 0.));
+        let rec = record(&r_in, Vec3::new(0., 1., 0.), &light);
+        assert!((0..100).all(|_| light.scatter(&r_in, &rec).is_none()));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::aabb::AABB;
+use crate::hittable::{BoxedHittable, HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::{Point3, Vec3};
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Volume of uniform density filling a closed boundary, such as fog or smoke.
+///
+/// A ray passing through the boundary scatters at a random distance, with
+/// exponentially decreasing probability, according to the `phase` material
+/// (usually `Isotropic`). Rays starting inside a non-convex boundary are not
+/// handled.
+pub struct ConstantMedium<T>
+where
+    T: Material + Send + Sync,
+{
+    boundary: BoxedHittable,
+    /// Negative inverse of the density, so that scattering distances are
+    /// `neg_inv_density * ln(xi)`
+    neg_inv_density: f64,
+    phase: T,
+}
+
+impl<T> ConstantMedium<T>
+where
+    T: Material + Send + Sync,
+{
+    pub fn new(boundary: BoxedHittable, density: f64, phase: T) -> ConstantMedium<T> {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase,
+        }
+    }
+}
+
+impl<T> Hittable for ConstantMedium<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Entry and exit of the boundary along the whole line, then clipped to the ray
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+        let t_enter = entry.t.max(t_min);
+        let t_exit = exit.t.min(t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let ray_length = r.direction().length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * random_double().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+        let t = t_enter + hit_distance / ray_length;
+        // The normal is arbitrary, the phase function does not use it
+        Some(HitRecord::new(
+            r,
+            r.at(t),
+            Vec3::new(1., 0., 0.),
+            t,
+            &self.phase,
+        ))
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+/// Densities on a regular grid of voxels.
+///
+/// Grid files are little-endian: three `u32` giving the number of voxels along
+/// x, y and z, followed by one `f32` density per voxel with x varying fastest,
+/// then y, then z.
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    values: Vec<f64>,
+}
+
+impl VoxelGrid {
+    pub fn load(path: &Path) -> io::Result<VoxelGrid> {
+        VoxelGrid::from_bytes(&fs::read(path)?)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<VoxelGrid> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        if bytes.len() < 12 {
+            return Err(invalid("voxel grid header is truncated"));
+        }
+        let (header, data) = bytes.split_at(12);
+        let mut dims = [0; 3];
+        for (dim, word) in dims.iter_mut().zip(header.chunks_exact(4)) {
+            *dim = u32::from_le_bytes(word.try_into().unwrap()) as usize;
+        }
+        if dims.contains(&0) {
+            return Err(invalid("voxel grid has no voxels"));
+        }
+        let count = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]));
+        if count.and_then(|n| n.checked_mul(4)) != Some(data.len()) {
+            return Err(invalid("voxel grid size does not match its dimensions"));
+        }
+        let values: Vec<f64> = data
+            .chunks_exact(4)
+            .map(|word| f64::from(f32::from_le_bytes(word.try_into().unwrap())))
+            .collect();
+        if values.iter().any(|d| !d.is_finite() || *d < 0.) {
+            return Err(invalid("voxel densities must be finite and non-negative"));
+        }
+        Ok(VoxelGrid { dims, values })
+    }
+
+    fn max_value(&self) -> f64 {
+        self.values.iter().fold(0., |max, &d| d.max(max))
+    }
+
+    /// Trilinear interpolation between voxel centers, for `uvw` in the unit
+    /// cube spanned by the grid.
+    fn density(&self, uvw: [f64; 3]) -> f64 {
+        let mut cell = [[0; 2]; 3];
+        let mut frac = [0.; 3];
+        for a in 0..3 {
+            let last = self.dims[a] - 1;
+            let x = (uvw[a] * self.dims[a] as f64 - 0.5).clamp(0., last as f64);
+            let i = (x as usize).min(last);
+            cell[a] = [i, (i + 1).min(last)];
+            frac[a] = x - i as f64;
+        }
+        let mut accum = 0.;
+        for (di, wi) in [(0, 1. - frac[0]), (1, frac[0])] {
+            for (dj, wj) in [(0, 1. - frac[1]), (1, frac[1])] {
+                for (dk, wk) in [(0, 1. - frac[2]), (1, frac[2])] {
+                    let idx =
+                        cell[0][di] + self.dims[0] * (cell[1][dj] + self.dims[1] * cell[2][dk]);
+                    accum += wi * wj * wk * self.values[idx];
+                }
+            }
+        }
+        accum
+    }
+}
+
+/// Volume of varying density, given by a voxel grid stretched over a box.
+///
+/// Scattering distances are sampled with delta tracking: tentative collisions
+/// are drawn as in a uniform medium of density `majorant`, the maximum density
+/// of the grid, and each is accepted with probability `density / majorant`.
+/// Rejected (null) collisions leave the ray unchanged, which keeps the
+/// estimate unbiased without stepping through the voxels.
+pub struct GridMedium<T>
+where
+    T: Material + Send + Sync,
+{
+    grid: VoxelGrid,
+    bbox: AABB,
+    /// Factor applied to the densities of the grid
+    density_scale: f64,
+    majorant: f64,
+    phase: T,
+}
+
+impl<T> GridMedium<T>
+where
+    T: Material + Send + Sync,
+{
+    pub fn new(grid: VoxelGrid, bbox: AABB, density_scale: f64, phase: T) -> GridMedium<T> {
+        let majorant = grid.max_value() * density_scale;
+        GridMedium {
+            grid,
+            bbox,
+            density_scale,
+            majorant,
+            phase,
+        }
+    }
+
+    fn density(&self, p: Point3) -> f64 {
+        let size = self.bbox.max - self.bbox.min;
+        let uvw = [0, 1, 2].map(|a| (p[a] - self.bbox.min[a]) / size[a]);
+        self.grid.density(uvw) * self.density_scale
+    }
+}
+
+impl<T> Hittable for GridMedium<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if self.majorant <= 0. {
+            return None;
+        }
+        let (t_enter, t_exit) =
+            self.bbox
+                .ray_interval(r.origin(), r.inv_direction(), t_min, t_max)?;
+        let ray_length = r.direction().length();
+        let mut t = t_enter;
+        loop {
+            t -= (1. - random_double()).ln() / (self.majorant * ray_length);
+            if t >= t_exit {
+                return None;
+            }
+            let p = r.at(t);
+            if random_double() * self.majorant < self.density(p) {
+                return Some(HitRecord::new(r, p, Vec3::new(1., 0., 0.), t, &self.phase));
+            }
+        }
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::material::Isotropic;
+    use crate::vec3::{color, point3};
+
+    fn fog(density: f64) -> ConstantMedium<Isotropic> {
+        let phase = Isotropic::new(color(1., 1., 1.));
+        let boundary = Box::new(Sphere::new(point3(0., 0., 0.), 1., phase.clone()));
+        ConstantMedium::new(boundary, density, phase)
+    }
+
+    #[test]
+    fn transmittance_follows_beer_lambert() {
+        let medium = fog(0.5);
+        // Crosses a diameter of the unit sphere, so 2 units of medium
+        let r = Ray::new(point3(0., 0., -5.), Vec3::new(0., 0., 2.));
+        let n = 100000;
+        let mut passed = 0;
+        for _ in 0..n {
+            match medium.hit(&r, 0.001, f64::INFINITY) {
+                Some(rec) => assert!(rec.p.z() >= -1. - 1e-9 && rec.p.z() <= 1. + 1e-9),
+                None => passed += 1,
+            }
+        }
+        let expected = (-0.5f64 * 2.).exp();
+        assert!((f64::from(passed) / f64::from(n) - expected).abs() < 0.01);
+    }
+
+    fn grid_bytes(dims: [u32; 3], values: &[f32]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for d in dims {
+            bytes.extend_from_slice(&d.to_le_bytes());
+        }
+        for v in values {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_voxel_grids() {
+        let grid = VoxelGrid::from_bytes(&grid_bytes([2, 1, 1], &[0., 2.])).unwrap();
+        assert_eq!(grid.max_value(), 2.);
+        // Constant up to the voxel centers, then linear in between
+        assert_eq!(grid.density([0.1, 0.5, 0.5]), 0.);
+        assert_eq!(grid.density([0.5, 0.5, 0.5]), 1.);
+        assert_eq!(grid.density([0.9, 0.5, 0.5]), 2.);
+
+        assert!(VoxelGrid::from_bytes(&grid_bytes([2, 2, 1], &[0., 2.])).is_err());
+        assert!(VoxelGrid::from_bytes(&grid_bytes([1, 1, 1], &[-1.])).is_err());
+        assert!(VoxelGrid::from_bytes(&[0; 8]).is_err());
+    }
+
+    #[test]
+    fn delta_tracking_matches_transmittance() {
+        // Voxel centers at x = 0.125, 0.375, 0.625 and 0.875
+        let grid = VoxelGrid::from_bytes(&grid_bytes([4, 1, 1], &[0., 0., 2., 2.])).unwrap();
+        let bbox = AABB::new(point3(0., 0., 0.), point3(1., 1., 1.));
+        let medium = GridMedium::new(grid, bbox, 1., Isotropic::new(color(1., 1., 1.)));
+        let r = Ray::new(point3(-1., 0.5, 0.5), Vec3::new(1., 0., 0.));
+        let n = 100000;
+        let mut passed = 0;
+        for _ in 0..n {
+            match medium.hit(&r, 0.001, f64::INFINITY) {
+                // No collisions where the density is zero
+                Some(rec) => assert!(rec.p.x() > 0.375),
+                None => passed += 1,
+            }
+        }
+        // Ramp from 0 to 2 over [0.375, 0.625], then 2 up to 1
+        let optical_depth = 0.25 + 2. * 0.375;
+        let expected = f64::exp(-optical_depth);
+        assert!((f64::from(passed) / f64::from(n) - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn scatters_only_inside_ray_interval() {
+        let medium = fog(1e6);
+        // Starting inside the boundary, a dense medium scatters right away
+        let r = Ray::new(point3(0., 0., 0.), Vec3::new(1., 0., 0.));
+        let rec = medium.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.t >= 0.001 && rec.t < 0.01);
+        // Nothing to scatter in before the ray enters the boundary
+        let r = Ray::new(point3(-5., 0., 0.), Vec3::new(1., 0., 0.));
+        assert!(medium.hit(&r, 0.001, 3.).is_none());
+    }
+}
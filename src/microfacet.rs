@@ -0,0 +1,435 @@
+use crate::hittable::HitRecord;
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::{color, cross, dot, reflect, unit_vector, Color, Vec3};
+use std::f64::consts::PI;
+
+/// Width of the distribution under which a surface is taken to be perfectly
+/// smooth, and sampled as a mirror.
+const MIN_ALPHA: f64 = 1e-3;
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals, with Smith's
+/// height-correlated masking. Directions are given in the shading frame, where
+/// the macroscopic normal is z.
+#[derive(Copy, Clone, Debug)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// Distribution for a perceptual `roughness` in `[0, 1]`, squared into the
+    /// width of the distribution.
+    pub fn new(roughness: f64) -> Ggx {
+        let roughness = roughness.clamp(0., 1.);
+        Ggx {
+            alpha: roughness * roughness,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < MIN_ALPHA
+    }
+
+    /// Density of microfacets with normal `h`, per unit of macroscopic area.
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z() <= 0. {
+            return 0.;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = h.z() * h.z() * (a2 - 1.) + 1.;
+        a2 / (PI * t * t)
+    }
+
+    /// Smith's auxiliary function, from which the masking terms follow.
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        let tan2 = (1. - cos2).max(0.) / cos2;
+        ((1. + self.alpha * self.alpha * tan2).sqrt() - 1.) / 2.
+    }
+
+    /// Fraction of the microfacets facing `w` that are visible from it.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `wo`, which is sampled by
+    /// `sample_visible`.
+    pub fn visible_d(&self, wo: Vec3, h: Vec3) -> f64 {
+        self.g1(wo) * dot(wo, h).max(0.) * self.d(h) / wo.z()
+    }
+
+    /// Sample a microfacet normal visible from `wo`, by stretching the
+    /// distribution into a hemisphere and sampling its projection (Heitz 2018).
+    pub fn sample_visible(&self, wo: Vec3) -> Vec3 {
+        let vh = unit_vector(&Vec3::new(self.alpha * wo.x(), self.alpha * wo.y(), wo.z()));
+        let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if len2 > 0. {
+            Vec3::new(-vh.y(), vh.x(), 0.) / len2.sqrt()
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let t2 = cross(vh, t1);
+
+        let r = random_double().sqrt();
+        let phi = 2. * PI * random_double();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z());
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+        unit_vector(&Vec3::new(
+            self.alpha * nh.x(),
+            self.alpha * nh.y(),
+            nh.z().max(0.),
+        ))
+    }
+}
+
+/// Reflectance of an interface between dielectrics for unpolarized light
+/// arriving at `cos_i` from the normal, where `eta` is the index on the far
+/// side over the index on the near side.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+/// Reflectance of a conductor of complex index `eta + i k` for unpolarized
+/// light arriving at `cos_i` from the normal.
+fn fresnel_conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1. - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2. * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+    (r_s + r_p) / 2.
+}
+
+/// Conductor reflectance, computed separately for each channel.
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    Vec3 {
+        e: [0, 1, 2].map(|c| fresnel_conductor_channel(cos_i, eta[c], k[c])),
+    }
+}
+
+/// Complex index of refraction `(eta, k)` of a common metal, at red, green and
+/// blue wavelengths.
+pub fn metal_ior(name: &str) -> Option<(Color, Color)> {
+    match name {
+        "gold" => Some((color(0.143, 0.374, 1.442), color(3.983, 2.385, 1.603))),
+        "copper" => Some((color(0.200, 0.924, 1.102), color(3.912, 2.452, 2.142))),
+        "aluminium" => Some((color(1.657, 0.880, 0.521), color(9.224, 6.270, 4.837))),
+        _ => None,
+    }
+}
+
+/// Shading frame at `rec`, with z along the normal on the side of the incoming
+/// ray, and the direction back along that ray in it.
+pub fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let onb = Onb::from_w(rec.get_normal());
+    let wo = onb.components(-unit_vector(&r_in.direction()));
+    (onb, wo)
+}
+
+/// Direction of `wo` refracted through a microfacet of normal `h`, into a
+/// medium whose index relative to the current one is `eta`. There must be no
+/// total internal reflection.
+pub fn refract_microfacet(wo: Vec3, h: Vec3, eta: f64) -> Vec3 {
+    let cos_o = dot(wo, h);
+    let cos_t = (1. - (1. - cos_o * cos_o) / (eta * eta)).sqrt();
+    -wo / eta + h * (cos_o / eta - cos_t)
+}
+
+/// Microfacet normal of a dielectric interface of relative index `eta`
+/// turning `wo` into `wi`, with the Fresnel reflectance and the density of
+/// reaching `wi` from it per unit solid angle, or `None` if no normal facing
+/// `wo` does.
+pub fn dielectric_half_vector(wo: Vec3, wi: Vec3, eta: f64) -> Option<(Vec3, f64, f64)> {
+    if wi.z() > 0. {
+        let h = unit_vector(&(wo + wi));
+        let f = fresnel_dielectric(dot(wo, h), eta);
+        return Some((h, f, 1. / (4. * dot(wo, h))));
+    }
+    let mut h = unit_vector(&(wo + wi * eta));
+    if h.z() < 0. {
+        h = -h;
+    }
+    let (cos_o, cos_i) = (dot(wo, h), dot(wi, h));
+    if cos_o <= 0. || cos_i >= 0. {
+        return None;
+    }
+    let denom = eta * cos_i + cos_o;
+    let f = fresnel_dielectric(cos_o, eta);
+    Some((h, f, eta * eta * -cos_i / (denom * denom)))
+}
+
+/// Metal with a rough surface of GGX microfacets, each reflecting light
+/// according to the Fresnel equations for the complex index of the metal.
+#[derive(Copy, Clone)]
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Conductor {
+        Conductor {
+            eta,
+            k,
+            distribution: Ggx::new(roughness),
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let (onb, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let (wi, attenuation) = if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            (wi, fresnel_conductor(wo.z(), self.eta, self.k))
+        } else {
+            let h = self.distribution.sample_visible(wo);
+            let wi = reflect(-wo, h);
+            if wi.z() <= 0. {
+                return None;
+            }
+            let masking = self.distribution.g2(wo, wi) / self.distribution.g1(wo);
+            (
+                wi,
+                fresnel_conductor(dot(wo, h), self.eta, self.k) * masking,
+            )
+        };
+        let direction = onb.local(wi.x(), wi.y(), wi.z());
+        Some((attenuation, Ray::with_time(rec.p, direction, r_in.time())))
+    }
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let (onb, wo) = shading_frame(r_in, rec);
+        let wi = onb.components(unit_vector(&direction));
+        if self.distribution.is_smooth() || wo.z() <= 0. || wi.z() <= 0. {
+            return color(0., 0., 0.);
+        }
+        let h = unit_vector(&(wo + wi));
+        let d = self.distribution.d(h);
+        let g = self.distribution.g2(wo, wi);
+        fresnel_conductor(dot(wo, h), self.eta, self.k) * (d * g / (4. * wo.z()))
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo) = shading_frame(r_in, rec);
+        let wi = onb.components(unit_vector(&direction));
+        if self.distribution.is_smooth() || wo.z() <= 0. || wi.z() <= 0. {
+            return 0.;
+        }
+        let h = unit_vector(&(wo + wi));
+        self.distribution.visible_d(wo, h) / (4. * dot(wo, h))
+    }
+}
+
+/// Glass with a rough surface of GGX microfacets, each reflecting or
+/// refracting light like a smooth interface (Walter et al. 2007).
+#[derive(Copy, Clone)]
+pub struct RoughDielectric {
+    ior: f64,
+    distribution: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(ior: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {
+            ior,
+            distribution: Ggx::new(roughness),
+        }
+    }
+
+    /// Index on the far side of the surface over the index on the side of the
+    /// incoming ray.
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            self.ior
+        } else {
+            1. / self.ior
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let (onb, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let eta = self.eta(rec);
+        let smooth = self.distribution.is_smooth();
+        let h = if smooth {
+            Vec3::new(0., 0., 1.)
+        } else {
+            self.distribution.sample_visible(wo)
+        };
+        let f = fresnel_dielectric(dot(wo, h), eta);
+        // Radiance is compressed into a smaller solid angle on entering a
+        // denser medium
+        let reflect_ray = random_double() < f;
+        let (wi, mut weight) = if reflect_ray {
+            (reflect(-wo, h), 1.)
+        } else {
+            (refract_microfacet(wo, h, eta), 1. / (eta * eta))
+        };
+        if !smooth {
+            // Microfacets can send light to the wrong side of the surface
+            if (wi.z() > 0.) != reflect_ray {
+                return None;
+            }
+            weight *= self.distribution.g2(wo, wi) / self.distribution.g1(wo);
+        }
+        let direction = onb.local(wi.x(), wi.y(), wi.z());
+        let attenuation = color(weight, weight, weight);
+        Some((attenuation, Ray::with_time(rec.p, direction, r_in.time())))
+    }
+    fn is_specular(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let (onb, wo) = shading_frame(r_in, rec);
+        let wi = onb.components(unit_vector(&direction));
+        if self.distribution.is_smooth() || wo.z() <= 0. || wi.z() == 0. {
+            return color(0., 0., 0.);
+        }
+        let eta = self.eta(rec);
+        let value = match dielectric_half_vector(wo, wi, eta) {
+            Some((h, f, jacobian)) => {
+                let lobe = if wi.z() > 0. {
+                    f
+                } else {
+                    (1. - f) / (eta * eta)
+                };
+                let d = self.distribution.d(h);
+                let g = self.distribution.g2(wo, wi);
+                lobe * d * g * dot(wo, h) * jacobian / wo.z()
+            }
+            None => 0.,
+        };
+        color(value, value, value)
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo) = shading_frame(r_in, rec);
+        let wi = onb.components(unit_vector(&direction));
+        if self.distribution.is_smooth() || wo.z() <= 0. || wi.z() == 0. {
+            return 0.;
+        }
+        match dielectric_half_vector(wo, wi, self.eta(rec)) {
+            Some((h, f, jacobian)) => {
+                let lobe = if wi.z() > 0. { f } else { 1. - f };
+                lobe * self.distribution.visible_d(wo, h) * jacobian
+            }
+            None => 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{assert_scatter_matches_eval, record};
+    use crate::vec3::{point3, random_unit_vector};
+
+    #[test]
+    fn fresnel_limits() {
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert!(fresnel_dielectric(1e-9, 1.5) > 0.999);
+        // Total internal reflection past the critical angle
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+
+        let (eta, k) = metal_ior("gold").unwrap();
+        let normal = fresnel_conductor(1., eta, k);
+        for c in 0..3 {
+            let expected =
+                ((eta[c] - 1.).powi(2) + k[c] * k[c]) / ((eta[c] + 1.).powi(2) + k[c] * k[c]);
+            assert!((normal[c] - expected).abs() < 1e-9);
+        }
+        // Gold reflects red more than blue, and everything at grazing angles
+        assert!(normal.x() > normal.z());
+        assert!(fresnel_conductor(1e-6, eta, k).x() > 0.999);
+        assert!(metal_ior("tin").is_none());
+    }
+
+    #[test]
+    fn scatter_weight_matches_eval_over_pdf() {
+        let (eta, k) = metal_ior("copper").unwrap();
+        let materials: [Box<dyn Material + Send + Sync>; 2] = [
+            Box::new(Conductor::new(eta, k, 0.4)),
+            Box::new(RoughDielectric::new(1.5, 0.4)),
+        ];
+        let r_in = Ray::new(point3(-1., 1., 0.), Vec3::new(1., -1., 0.));
+        for mat in materials.iter() {
+            // From outside, and from inside the glass
+            for &outward in [Vec3::new(0., 1., 0.), Vec3::new(0., -1., 0.)].iter() {
+                assert_scatter_matches_eval(mat.as_ref(), &r_in, outward);
+            }
+        }
+    }
+
+    #[test]
+    fn pdfs_integrate_to_scattered_fraction() {
+        // Rough surfaces lose the directions reflected below the horizon, so
+        // the densities integrate to the fraction of successful samples
+        let (eta, k) = metal_ior("aluminium").unwrap();
+        let conductor = Conductor::new(eta, k, 0.6);
+        let glass = RoughDielectric::new(1.5, 0.6);
+        let r_in = Ray::new(point3(0., 1., 0.), Vec3::new(0.5, -1., 0.));
+        let outward = Vec3::new(0., 1., 0.);
+        let n = 200000;
+        for mat in [&conductor as &(dyn Material + Send + Sync), &glass].iter() {
+            let rec = record(&r_in, outward, *mat);
+            let mut total = 0.;
+            let mut scattered = 0;
+            for _ in 0..n {
+                // Half of the directions are uniform and half are sampled by
+                // the material, which keeps the weights below 2
+                let direction = if random_double() < 0.5 {
+                    Some(random_unit_vector())
+                } else {
+                    mat.scatter(&r_in, &rec).map(|(_, ray)| ray.direction())
+                };
+                if let Some(direction) = direction {
+                    let pdf = mat.pdf(&r_in, &rec, direction);
+                    total += pdf / (0.5 / (4. * PI) + 0.5 * pdf);
+                }
+                if mat.scatter(&r_in, &rec).is_some() {
+                    scattered += 1;
+                }
+            }
+            let integral = total / f64::from(n);
+            let fraction = f64::from(scattered) / f64::from(n);
+            assert!(fraction < 0.99);
+            assert!(
+                (integral - fraction).abs() < 0.02,
+                "{} {}",
+                integral,
+                fraction
+            );
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use crate::material::{Dielectric, Lambertian, Material, Metal};
+use crate::texture::ImageTexture;
+use crate::triangle::{Face, MeshData, TriangleMesh};
+use crate::vec3::{color, Color, Point3, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const DEFAULT_COLOR: f64 = 0.8;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    Parse {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    NoFaces(String),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ObjError::Parse {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file, line, message),
+            ObjError::NoFaces(file) => write!(f, "{}: mesh has no faces", file),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// A material as described in an MTL file.
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Color,
+    /// PNG image replacing the diffuse color (`map_Kd`)
+    pub diffuse_map: Option<PathBuf>,
+    pub specular: Color,
+    pub shininess: f64,
+    pub ior: f64,
+    pub dissolve: f64,
+    pub illum: Option<u32>,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: name.to_string(),
+            diffuse: color(DEFAULT_COLOR, DEFAULT_COLOR, DEFAULT_COLOR),
+            diffuse_map: None,
+            specular: color(0., 0., 0.),
+            shininess: 0.,
+            ior: 1.5,
+            dissolve: 1.,
+            illum: None,
+        }
+    }
+
+    /// Pick the closest of our materials: transparent materials become `Dielectric`,
+    /// mostly specular ones `Metal` (with `Ns` mapped to fuzz) and the rest `Lambertian`,
+    /// textured by the diffuse map if there is one.
+    pub fn to_material(&self) -> Result<Box<dyn Material + Send + Sync>, ObjError> {
+        let transparent = self.dissolve < 1. || matches!(self.illum, Some(4) | Some(6) | Some(7));
+        let mirror = matches!(self.illum, Some(3) | Some(5))
+            || self.specular.max_component() > self.diffuse.max_component();
+        Ok(if transparent {
+            Box::new(Dielectric::new(self.ior))
+        } else if mirror {
+            let fuzz = (2. / (self.shininess.max(0.) + 2.)).sqrt();
+            Box::new(Metal::new(self.specular, fuzz))
+        } else if let Some(path) = &self.diffuse_map {
+            let image = ImageTexture::load(path).map_err(|e| ObjError::Io(path.clone(), e))?;
+            Box::new(Lambertian::textured(Arc::new(image)))
+        } else {
+            Box::new(Lambertian::new(self.diffuse))
+        })
+    }
+}
+
+/// Load a Wavefront OBJ file, resolving `mtllib` statements relative to its directory.
+pub fn load_obj(path: &Path) -> Result<TriangleMesh, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let data = parse_obj(
+        BufReader::new(file),
+        &path.display().to_string(),
+        |name: &str| load_mtl(&base_dir.join(name)),
+    )?;
+    let n_faces = data.faces.len();
+    let mesh = TriangleMesh::new(data);
+    eprintln!(
+        "Loaded {}: {} triangles, BVH: {}",
+        path.display(),
+        n_faces,
+        mesh.bvh_stats()
+    );
+    Ok(mesh)
+}
+
+/// Load an MTL file, resolving texture maps relative to its directory.
+pub fn load_mtl(path: &Path) -> Result<Vec<MtlMaterial>, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = parse_mtl(BufReader::new(file), &path.display().to_string())?;
+    for map in materials.iter_mut().filter_map(|m| m.diffuse_map.as_mut()) {
+        *map = base_dir.join(&map);
+    }
+    Ok(materials)
+}
+
+struct LineError(String);
+
+fn parse_error(file: &str, line: usize, LineError(message): LineError) -> ObjError {
+    ObjError::Parse {
+        file: file.to_string(),
+        line,
+        message,
+    }
+}
+
+fn parse_f64(token: Option<&str>, what: &str) -> Result<f64, LineError> {
+    let token = token.ok_or_else(|| LineError(format!("missing {}", what)))?;
+    token
+        .parse()
+        .ok()
+        .filter(|x: &f64| x.is_finite())
+        .ok_or_else(|| LineError(format!("invalid {} '{}'", what, token)))
+}
+
+fn parse_vec3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Result<Vec3, LineError> {
+    Ok(Vec3::new(
+        parse_f64(tokens.next(), "x coordinate")?,
+        parse_f64(tokens.next(), "y coordinate")?,
+        parse_f64(tokens.next(), "z coordinate")?,
+    ))
+}
+
+/// Resolve a 1-based (or negative, relative to the end) OBJ index.
+fn resolve_index(token: &str, count: usize, what: &str) -> Result<usize, LineError> {
+    let idx: i64 = token
+        .parse()
+        .map_err(|_| LineError(format!("invalid {} index '{}'", what, token)))?;
+    let resolved = if idx > 0 { idx - 1 } else { count as i64 + idx };
+    if idx == 0 || resolved < 0 || resolved >= count as i64 {
+        Err(LineError(format!(
+            "{} index {} out of range ({} defined)",
+            what, idx, count
+        )))
+    } else {
+        Ok(resolved as usize)
+    }
+}
+
+pub fn parse_obj<R, F>(reader: R, file: &str, mut load_library: F) -> Result<MeshData, ObjError>
+where
+    R: BufRead,
+    F: FnMut(&str) -> Result<Vec<MtlMaterial>, ObjError>,
+{
+    let mut positions: Vec<Point3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut texcoords: Vec<(f64, f64)> = vec![];
+    let mut faces = vec![];
+    let mut library: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut materials: Vec<Box<dyn Material + Send + Sync>> = vec![];
+    let mut material_ids: HashMap<String, usize> = HashMap::new();
+    let mut current_material: Option<usize> = None;
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let line = line.map_err(|e| ObjError::Io(PathBuf::from(file), e))?;
+        let content = line.split('#').next().unwrap_or("");
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let err = |e| parse_error(file, line_no, e);
+        match keyword {
+            "v" => positions.push(parse_vec3(&mut tokens).map_err(err)?),
+            "vn" => {
+                let normal = parse_vec3(&mut tokens).map_err(err)?;
+                if normal.length_squared() == 0. {
+                    return Err(err(LineError("zero-length normal".into())));
+                }
+                normals.push(normal);
+            }
+            "vt" => {
+                let u = parse_f64(tokens.next(), "u coordinate").map_err(err)?;
+                let v = match tokens.next() {
+                    Some(token) => parse_f64(Some(token), "v coordinate").map_err(err)?,
+                    None => 0.,
+                };
+                texcoords.push((u, v));
+            }
+            "f" => {
+                let mut corners = vec![];
+                for corner in tokens {
+                    let mut parts = corner.split('/');
+                    let v = parts.next().unwrap_or("");
+                    let vt = parts.next().unwrap_or("");
+                    let vn = parts.next().unwrap_or("");
+                    let v = resolve_index(v, positions.len(), "vertex").map_err(err)?;
+                    let vt = if vt.is_empty() {
+                        None
+                    } else {
+                        Some(
+                            resolve_index(vt, texcoords.len(), "texture coordinate")
+                                .map_err(err)?,
+                        )
+                    };
+                    let vn = if vn.is_empty() {
+                        None
+                    } else {
+                        Some(resolve_index(vn, normals.len(), "normal").map_err(err)?)
+                    };
+                    corners.push((v, vt, vn));
+                }
+                if corners.len() < 3 {
+                    return Err(err(LineError(format!(
+                        "face needs at least 3 vertices, got {}",
+                        corners.len()
+                    ))));
+                }
+                let material = *current_material.get_or_insert_with(|| {
+                    materials.push(Box::new(Lambertian::new(color(
+                        DEFAULT_COLOR,
+                        DEFAULT_COLOR,
+                        DEFAULT_COLOR,
+                    ))));
+                    materials.len() - 1
+                });
+                // Triangulate polygons as a fan around the first corner
+                for i in 1..corners.len() - 1 {
+                    let tri = [corners[0], corners[i], corners[i + 1]];
+                    let texcoords = match tri {
+                        [(_, Some(t0), _), (_, Some(t1), _), (_, Some(t2), _)] => {
+                            Some([t0, t1, t2])
+                        }
+                        _ => None,
+                    };
+                    let normals = match tri {
+                        [(_, _, Some(n0)), (_, _, Some(n1)), (_, _, Some(n2))] => {
+                            Some([n0, n1, n2])
+                        }
+                        _ => None,
+                    };
+                    faces.push(Face {
+                        vertices: [tri[0].0, tri[1].0, tri[2].0],
+                        normals,
+                        texcoords,
+                        material,
+                    });
+                }
+            }
+            "mtllib" => {
+                let names: Vec<&str> = tokens.collect();
+                if names.is_empty() {
+                    return Err(err(LineError("missing material library name".into())));
+                }
+                for name in names {
+                    for mat in load_library(name)? {
+                        library.insert(mat.name.clone(), mat);
+                    }
+                }
+            }
+            "usemtl" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| err(LineError("missing material name".into())))?;
+                let id = match material_ids.get(name) {
+                    Some(id) => *id,
+                    None => {
+                        let mat = library.get(name).ok_or_else(|| {
+                            err(LineError(format!("unknown material '{}'", name)))
+                        })?;
+                        materials.push(mat.to_material()?);
+                        material_ids.insert(name.to_string(), materials.len() - 1);
+                        materials.len() - 1
+                    }
+                };
+                current_material = Some(id);
+            }
+            // Grouping, smoothing, curves and the like do not affect rendering
+            _ => {}
+        }
+    }
+
+    if faces.is_empty() {
+        return Err(ObjError::NoFaces(file.to_string()));
+    }
+    Ok(MeshData {
+        positions,
+        normals,
+        texcoords,
+        faces,
+        materials,
+    })
+}
+
+pub fn parse_mtl<R: BufRead>(reader: R, file: &str) -> Result<Vec<MtlMaterial>, ObjError> {
+    let mut materials: Vec<MtlMaterial> = vec![];
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let line = line.map_err(|e| ObjError::Io(PathBuf::from(file), e))?;
+        let content = line.split('#').next().unwrap_or("");
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let err = |e| parse_error(file, line_no, e);
+        if keyword == "newmtl" {
+            let name = tokens
+                .next()
+                .ok_or_else(|| err(LineError("missing material name".into())))?;
+            materials.push(MtlMaterial::new(name));
+            continue;
+        }
+        let current = materials.last_mut().ok_or_else(|| {
+            err(LineError(format!(
+                "'{}' appears before any 'newmtl'",
+                keyword
+            )))
+        })?;
+        match keyword {
+            "Kd" => current.diffuse = parse_vec3(&mut tokens).map_err(err)?,
+            "Ks" => current.specular = parse_vec3(&mut tokens).map_err(err)?,
+            "Ns" => current.shininess = parse_f64(tokens.next(), "Ns").map_err(err)?,
+            "Ni" => current.ior = parse_f64(tokens.next(), "Ni").map_err(err)?,
+            "d" => current.dissolve = parse_f64(tokens.next(), "d").map_err(err)?,
+            "Tr" => current.dissolve = 1. - parse_f64(tokens.next(), "Tr").map_err(err)?,
+            // Options such as `-clamp on` come before the file name
+            "map_Kd" => {
+                let name = tokens
+                    .last()
+                    .ok_or_else(|| err(LineError("missing texture file name".into())))?;
+                current.diffuse_map = Some(PathBuf::from(name));
+            }
+            "illum" => {
+                let value = parse_f64(tokens.next(), "illum").map_err(err)?;
+                current.illum = Some(value as u32);
+            }
+            // Everything else (ambient, emission, other texture maps...) is not supported
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+    use crate::vec3::point3;
+
+    const MTL: &str = "
+newmtl red
+Kd 0.8 0.1 0.1
+newmtl chrome
+Kd 0 0 0
+Ks 0.9 0.9 0.9
+Ns 500
+newmtl glass
+Ni 1.45
+d 0.2
+newmtl wood
+map_Kd -clamp on missing.png
+";
+
+    fn parse(obj: &str) -> Result<MeshData, ObjError> {
+        parse_obj(obj.as_bytes(), "test.obj", |_: &str| {
+            parse_mtl(MTL.as_bytes(), "test.mtl")
+        })
+    }
+
+    #[test]
+    fn parses_quad_with_materials() {
+        let mesh = parse(
+            "mtllib test.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+usemtl red
+f 1//1 2//1 3//1 4//1
+usemtl glass
+f -4 -3 -2
+",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.faces.len(), 3);
+        assert_eq!(mesh.faces[1].vertices, [0, 2, 3]);
+        assert_eq!(mesh.faces[1].normals, Some([0, 0, 0]));
+        assert_eq!(mesh.faces[2].normals, None);
+        assert_eq!(mesh.faces[2].material, 1);
+        assert_eq!(mesh.materials.len(), 2);
+    }
+
+    #[test]
+    fn reports_line_of_malformed_statements() {
+        let err = parse("v 0 0 0\nv 1 0 zero\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.obj:2: invalid z coordinate 'zero'");
+        let err = parse("v 0 nan 0\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.obj:1: invalid y coordinate 'nan'");
+        let err = parse("v 0 0 0\nvt inf 0\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.obj:2: invalid u coordinate 'inf'");
+        let err = parse("v 0 0 0\nvn 0 0 0\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.obj:2: zero-length normal");
+
+        let err = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "test.obj:4: vertex index 4 out of range (3 defined)"
+        );
+
+        let err = parse("v 0 0 0\nf 1 1\n").err().unwrap();
+        assert!(matches!(err, ObjError::Parse { line: 2, .. }));
+
+        let err = parse("mtllib test.mtl\nusemtl oak\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.obj:2: unknown material 'oak'");
+
+        // Texture maps are loaded with the material
+        let err = parse("mtllib test.mtl\nusemtl wood\n").err().unwrap();
+        assert!(matches!(err, ObjError::Io(path, _) if path == Path::new("missing.png")));
+
+        assert!(matches!(parse("v 0 0 0\n"), Err(ObjError::NoFaces(_))));
+    }
+
+    #[test]
+    fn parses_mtl_properties() {
+        let mats = parse_mtl(MTL.as_bytes(), "test.mtl").unwrap();
+        assert_eq!(mats.len(), 4);
+        assert_eq!(mats[0].diffuse, color(0.8, 0.1, 0.1));
+        assert_eq!(mats[1].shininess, 500.);
+        assert_eq!(mats[2].ior, 1.45);
+        assert_eq!(mats[2].dissolve, 0.2);
+        assert_eq!(mats[3].diffuse_map, Some(PathBuf::from("missing.png")));
+
+        let err = parse_mtl("Kd 1 1 1\n".as_bytes(), "bad.mtl").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "bad.mtl:1: 'Kd' appears before any 'newmtl'"
+        );
+    }
+
+    #[test]
+    fn interpolates_texture_coordinates() {
+        let data = parse(
+            "v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0.5 0.5
+vt 1 0.5
+vt 0.5
+f 1/1 2/2 3/3
+",
+        )
+        .unwrap();
+        assert_eq!(data.texcoords, vec![(0.5, 0.5), (1., 0.5), (0.5, 0.)]);
+        assert_eq!(data.faces[0].texcoords, Some([0, 1, 2]));
+
+        let mesh = TriangleMesh::new(data);
+        let r = Ray::new(point3(0.5, 0.25, 1.), Vec3::new(0., 0., -1.));
+        let rec = mesh.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.u - 0.75).abs() < 1e-12);
+        assert!((rec.v - 0.375).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::vec3::{cross, dot, unit_vector, Vec3};
+
+/// Orthonormal basis built around a direction `w`.
+#[derive(Copy, Clone, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(n: Vec3) -> Onb {
+        let w = unit_vector(&n);
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let v = unit_vector(&cross(w, a));
+        let u = cross(w, v);
+        Onb { u, v, w }
+    }
+
+    /// Convert local coordinates to a world space vector.
+    pub fn local(&self, a: f64, b: f64, c: f64) -> Vec3 {
+        self.u * a + self.v * b + self.w * c
+    }
+
+    /// Local coordinates of a world space vector.
+    pub fn components(&self, v: Vec3) -> Vec3 {
+        Vec3::new(dot(v, self.u), dot(v, self.v), dot(v, self.w))
+    }
+}
@@ -0,0 +1,124 @@
+use crate::vec3::{dot, unit_vector, Point3, Vec3};
+use rand::prelude::*;
+
+const POINT_COUNT: usize = 256;
+
+/// Gradient noise on a lattice of random unit vectors, reproducible from a seed.
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let ranvec = (0..POINT_COUNT)
+            .map(|_| loop {
+                let v = Vec3::new(
+                    rng.gen_range(-1.0..1.),
+                    rng.gen_range(-1.0..1.),
+                    rng.gen_range(-1.0..1.),
+                );
+                // Rejection sampling keeps the directions uniform
+                if v.length_squared() > 1e-6 && v.length_squared() <= 1. {
+                    break unit_vector(&v);
+                }
+            })
+            .collect();
+        let mut permutation = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+        Perlin {
+            ranvec,
+            perm_x: permutation(),
+            perm_y: permutation(),
+            perm_z: permutation(),
+        }
+    }
+
+    /// Noise value in `[-1, 1]`, smooth in `p`.
+    pub fn noise(&self, p: Point3) -> f64 {
+        let floor = [p.x().floor(), p.y().floor(), p.z().floor()];
+        let frac = [p.x() - floor[0], p.y() - floor[1], p.z() - floor[2]];
+        let cell = [floor[0] as i64, floor[1] as i64, floor[2] as i64];
+        // Hermite smoothing of the interpolation weights
+        let smooth = frac.map(|t| t * t * (3. - 2. * t));
+
+        let mut accum = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let idx = self.perm_x[wrap(cell[0] + di)]
+                        ^ self.perm_y[wrap(cell[1] + dj)]
+                        ^ self.perm_z[wrap(cell[2] + dk)];
+                    let corner = [di as f64, dj as f64, dk as f64];
+                    let weight = Vec3::new(
+                        frac[0] - corner[0],
+                        frac[1] - corner[1],
+                        frac[2] - corner[2],
+                    );
+                    let mut w = 1.;
+                    for a in 0..3 {
+                        w *= corner[a] * smooth[a] + (1. - corner[a]) * (1. - smooth[a]);
+                    }
+                    accum += w * dot(self.ranvec[idx], weight);
+                }
+            }
+        }
+        accum
+    }
+
+    /// Sum of `octaves` layers of noise, each at twice the frequency and half
+    /// the amplitude of the previous one.
+    pub fn turbulence(&self, p: Point3, octaves: u32) -> f64 {
+        let mut accum = 0.;
+        let mut p = p;
+        let mut weight = 1.;
+        for _ in 0..octaves {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.;
+        }
+        accum.abs()
+    }
+}
+
+fn wrap(i: i64) -> usize {
+    i.rem_euclid(POINT_COUNT as i64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::point3;
+
+    #[test]
+    fn same_seed_same_noise() {
+        let (a, b, c) = (Perlin::new(3), Perlin::new(3), Perlin::new(4));
+        let p = point3(1.3, -2.7, 0.4);
+        assert_eq!(a.noise(p), b.noise(p));
+        assert_ne!(a.noise(p), c.noise(p));
+    }
+
+    #[test]
+    fn noise_is_bounded_and_continuous() {
+        let perlin = Perlin::new(1);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let p = point3(
+                rng.gen_range(-50.0..50.),
+                rng.gen_range(-50.0..50.),
+                rng.gen_range(-50.0..50.),
+            );
+            let n = perlin.noise(p);
+            assert!((-1. ..=1.).contains(&n));
+            assert!((perlin.noise(p + Vec3::new(1e-6, 0., 0.)) - n).abs() < 1e-4);
+        }
+        // Gradient noise vanishes on the lattice
+        assert_eq!(perlin.noise(point3(3., -2., 7.)), 0.);
+    }
+}
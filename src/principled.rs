@@ -0,0 +1,394 @@
+use crate::hittable::HitRecord;
+use crate::material::Material;
+use crate::microfacet::{
+    dielectric_half_vector, fresnel_dielectric, refract_microfacet, shading_frame, Ggx,
+};
+use crate::ray::Ray;
+use crate::texture::{SharedTexture, SolidColor};
+use crate::utils::random_double;
+use crate::vec3::{color, dot, random_unit_vector, reflect, unit_vector, Color, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Smoother surfaces are rendered at this roughness, so that every lobe can
+/// still be evaluated for light sampling.
+const MIN_ROUGHNESS: f64 = 0.05;
+/// Roughness of the clear coat layer.
+const CLEARCOAT_ROUGHNESS: f64 = 0.1;
+
+/// Parameters of a `Principled` material. Apart from the index of refraction,
+/// they are all between 0 and 1.
+#[derive(Clone)]
+pub struct PrincipledParams {
+    pub base_color: SharedTexture,
+    /// Blend from a dielectric to a metal reflecting the base color
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Strength of the reflection of dielectrics, 0.5 matching an index of 1.5
+    pub specular: f64,
+    /// Strength of a clear glossy layer over the surface, as on car paint
+    pub clearcoat: f64,
+    /// Strength of a white reflection at grazing angles, as on cloth
+    pub sheen: f64,
+    /// Blend from an opaque dielectric to glass tinted by the base color
+    pub transmission: f64,
+    /// Index of refraction of the transmissive part
+    pub ior: f64,
+}
+
+impl Default for PrincipledParams {
+    fn default() -> PrincipledParams {
+        PrincipledParams {
+            base_color: Arc::new(SolidColor::new(color(0.8, 0.8, 0.8))),
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            clearcoat: 0.,
+            sheen: 0.,
+            transmission: 0.,
+            ior: 1.5,
+        }
+    }
+}
+
+/// Weights of the lobes of a `Principled` material at a hit, which are also
+/// the odds of sampling each of them.
+struct Lobes {
+    diffuse: f64,
+    specular: f64,
+    clearcoat: f64,
+    transmission: f64,
+}
+
+impl Lobes {
+    fn total(&self) -> f64 {
+        self.diffuse + self.specular + self.clearcoat + self.transmission
+    }
+}
+
+/// Weight of Schlick's approximation of the Fresnel reflectance.
+fn schlick_weight(cosine: f64) -> f64 {
+    (1. - cosine).clamp(0., 1.).powi(5)
+}
+
+/// Disney's principled material (Burley 2012, 2015): a diffuse base with
+/// retro-reflection and sheen, GGX specular reflection blending from
+/// dielectric to metal, a clear coat, and rough transmission.
+///
+/// Each scattered ray follows one lobe, chosen in proportion to its weight,
+/// and is weighted by the whole material over the combined density.
+#[derive(Clone)]
+pub struct Principled {
+    params: PrincipledParams,
+    distribution: Ggx,
+    clearcoat_distribution: Ggx,
+}
+
+impl Principled {
+    pub fn new(params: PrincipledParams) -> Principled {
+        Principled {
+            distribution: Ggx::new(params.roughness.max(MIN_ROUGHNESS)),
+            clearcoat_distribution: Ggx::new(CLEARCOAT_ROUGHNESS),
+            params,
+        }
+    }
+
+    /// Rays inside a transmissive object only meet the glass lobe on their way
+    /// out.
+    fn lobes(&self, rec: &HitRecord) -> Lobes {
+        let p = &self.params;
+        if !rec.front_face && p.transmission > 0. {
+            return Lobes {
+                diffuse: 0.,
+                specular: 0.,
+                clearcoat: 0.,
+                transmission: 1.,
+            };
+        }
+        let dielectric = 1. - p.metallic;
+        Lobes {
+            diffuse: dielectric * (1. - p.transmission),
+            specular: p.metallic + dielectric * (1. - p.transmission),
+            clearcoat: 0.25 * p.clearcoat,
+            transmission: dielectric * p.transmission,
+        }
+    }
+
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            self.params.ior
+        } else {
+            1. / self.params.ior
+        }
+    }
+
+    /// BSDF times the cosine term, in the shading frame.
+    fn eval_local(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> Color {
+        let p = &self.params;
+        let lobes = self.lobes(rec);
+        let base = p.base_color.value(rec.u, rec.v, rec.p);
+        let white = color(1., 1., 1.);
+        let mut value = color(0., 0., 0.);
+        if wo.z() <= 0. || wi.z() == 0. {
+            return value;
+        }
+        if wi.z() > 0. {
+            let h = unit_vector(&(wo + wi));
+            let cos_d = dot(wi, h);
+            if lobes.diffuse > 0. {
+                let fd90 = 0.5 + 2. * p.roughness * cos_d * cos_d;
+                let retro = (1. + (fd90 - 1.) * schlick_weight(wi.z()))
+                    * (1. + (fd90 - 1.) * schlick_weight(wo.z()));
+                value += base * (lobes.diffuse * retro * wi.z() / PI);
+                value += white * (lobes.diffuse * p.sheen * schlick_weight(cos_d) * wi.z());
+            }
+            let fresnel = schlick_weight(cos_d);
+            if lobes.specular > 0. {
+                let tint = base * p.metallic + white * ((1. - p.metallic) * 0.08 * p.specular);
+                let f = tint + (white - tint) * fresnel;
+                let d = self.distribution.d(h);
+                let g = self.distribution.g2(wo, wi);
+                value += f * (lobes.specular * d * g / (4. * wo.z()));
+            }
+            if lobes.clearcoat > 0. {
+                let f = 0.04 + 0.96 * fresnel;
+                let d = self.clearcoat_distribution.d(h);
+                let g = self.clearcoat_distribution.g2(wo, wi);
+                value += white * (lobes.clearcoat * f * d * g / (4. * wo.z()));
+            }
+        }
+        if lobes.transmission > 0. {
+            let eta = self.eta(rec);
+            if let Some((h, f, jacobian)) = dielectric_half_vector(wo, wi, eta) {
+                let d = self.distribution.d(h);
+                let g = self.distribution.g2(wo, wi);
+                let lobe = lobes.transmission * d * g * dot(wo, h) * jacobian / wo.z();
+                value += if wi.z() > 0. {
+                    white * (f * lobe)
+                } else if rec.front_face {
+                    base * ((1. - f) / (eta * eta) * lobe)
+                } else {
+                    white * ((1. - f) / (eta * eta) * lobe)
+                };
+            }
+        }
+        value
+    }
+
+    /// Density of `scatter` choosing `wi`, in the shading frame.
+    fn pdf_local(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f64 {
+        let lobes = self.lobes(rec);
+        if wo.z() <= 0. || wi.z() == 0. {
+            return 0.;
+        }
+        let mut pdf = 0.;
+        if wi.z() > 0. {
+            let h = unit_vector(&(wo + wi));
+            pdf += lobes.diffuse * wi.z() / PI;
+            pdf += lobes.specular * self.distribution.visible_d(wo, h) / (4. * dot(wo, h));
+            pdf +=
+                lobes.clearcoat * self.clearcoat_distribution.visible_d(wo, h) / (4. * dot(wo, h));
+        }
+        if lobes.transmission > 0. {
+            if let Some((h, f, jacobian)) = dielectric_half_vector(wo, wi, self.eta(rec)) {
+                let lobe = if wi.z() > 0. { f } else { 1. - f };
+                pdf += lobes.transmission * lobe * self.distribution.visible_d(wo, h) * jacobian;
+            }
+        }
+        pdf / lobes.total()
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let (onb, wo) = shading_frame(r_in, rec);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let lobes = self.lobes(rec);
+        let mut pick = random_double() * lobes.total();
+        let wi = if pick < lobes.diffuse {
+            unit_vector(&(Vec3::new(0., 0., 1.) + random_unit_vector()))
+        } else {
+            pick -= lobes.diffuse;
+            if pick < lobes.specular {
+                reflect(-wo, self.distribution.sample_visible(wo))
+            } else if pick < lobes.specular + lobes.clearcoat {
+                reflect(-wo, self.clearcoat_distribution.sample_visible(wo))
+            } else {
+                let h = self.distribution.sample_visible(wo);
+                let eta = self.eta(rec);
+                if random_double() < fresnel_dielectric(dot(wo, h), eta) {
+                    reflect(-wo, h)
+                } else {
+                    refract_microfacet(wo, h, eta)
+                }
+            }
+        };
+        let pdf = self.pdf_local(rec, wo, wi);
+        if pdf <= 0. {
+            return None;
+        }
+        let attenuation = self.eval_local(rec, wo, wi) / pdf;
+        let direction = onb.local(wi.x(), wi.y(), wi.z());
+        Some((attenuation, Ray::with_time(rec.p, direction, r_in.time())))
+    }
+    fn is_specular(&self) -> bool {
+        false
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let (onb, wo) = shading_frame(r_in, rec);
+        self.eval_local(rec, wo, onb.components(unit_vector(&direction)))
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let (onb, wo) = shading_frame(r_in, rec);
+        self.pdf_local(rec, wo, onb.components(unit_vector(&direction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{assert_scatter_matches_eval, record};
+    use crate::vec3::point3;
+
+    fn principled(base: Color, params: PrincipledParams) -> Principled {
+        Principled::new(PrincipledParams {
+            base_color: Arc::new(SolidColor::new(base)),
+            ..params
+        })
+    }
+
+    /// Mean weight of the rays scattered at a hit, zero for those absorbed.
+    fn albedo(mat: &Principled, r_in: &Ray, outward: Vec3, n: u32) -> Color {
+        let rec = record(r_in, outward, mat);
+        let mut total = color(0., 0., 0.);
+        for _ in 0..n {
+            if let Some((attenuation, _)) = mat.scatter(r_in, &rec) {
+                total += attenuation;
+            }
+        }
+        total / f64::from(n)
+    }
+
+    #[test]
+    fn scatter_weight_matches_eval_over_pdf() {
+        let base = color(0.8, 0.5, 0.3);
+        let defaults = PrincipledParams::default;
+        let materials = [
+            principled(base, defaults()),
+            principled(
+                base,
+                PrincipledParams {
+                    metallic: 0.7,
+                    roughness: 0.2,
+                    clearcoat: 1.,
+                    sheen: 0.5,
+                    ..defaults()
+                },
+            ),
+            principled(
+                base,
+                PrincipledParams {
+                    transmission: 0.8,
+                    roughness: 0.3,
+                    ..defaults()
+                },
+            ),
+        ];
+        let r_in = Ray::new(point3(-1., 1., 0.), Vec3::new(1., -1., 0.));
+        for mat in materials.iter() {
+            for &outward in [Vec3::new(0., 1., 0.), Vec3::new(0., -1., 0.)].iter() {
+                assert_scatter_matches_eval(mat, &r_in, outward);
+            }
+        }
+    }
+
+    #[test]
+    fn parameters_blend_between_plastic_and_metal() {
+        let r_in = Ray::new(point3(0., 1., 0.), Vec3::new(0.1, -1., 0.));
+        let up = Vec3::new(0., 1., 0.);
+        let n = 20000;
+        let gold = color(1., 0.8, 0.3);
+        let metal = principled(
+            gold,
+            PrincipledParams {
+                metallic: 1.,
+                roughness: 0.2,
+                ..PrincipledParams::default()
+            },
+        );
+        // A metal reflects its base color, losing little to masking
+        let reflected = albedo(&metal, &r_in, up, n);
+        for c in 0..3 {
+            assert!(reflected[c] <= gold[c] + 0.02 && reflected[c] > 0.9 * gold[c]);
+        }
+
+        // Black plastic only keeps its specular highlight
+        let black = principled(color(0., 0., 0.), PrincipledParams::default());
+        let reflected = albedo(&black, &r_in, up, n);
+        assert!(reflected.max_component() < 0.1, "{:?}", reflected);
+        let coated = principled(
+            color(0., 0., 0.),
+            PrincipledParams {
+                clearcoat: 1.,
+                ..PrincipledParams::default()
+            },
+        );
+        assert!(albedo(&coated, &r_in, up, n).x() > reflected.x());
+    }
+
+    #[test]
+    fn transmission_passes_light_through() {
+        let glass = principled(
+            color(1., 1., 1.),
+            PrincipledParams {
+                transmission: 1.,
+                roughness: 0.1,
+                ..PrincipledParams::default()
+            },
+        );
+        let r_in = Ray::new(point3(0., 1., 0.), Vec3::new(0.1, -1., 0.));
+        let up = Vec3::new(0., 1., 0.);
+        let rec = record(&r_in, up, &glass);
+        let n = 2000;
+        let through = (0..n)
+            .filter_map(|_| glass.scatter(&r_in, &rec))
+            .filter(|(_, ray)| ray.direction().y() < 0.)
+            .count();
+        // About 4% is reflected at normal incidence
+        assert!(through > n * 9 / 10, "{} rays through", through);
+
+        // Leaving the glass again, without losing energy past the radiance
+        // scaling on the way in
+        let inside = Ray::new(point3(0., -1., 0.), Vec3::new(0.1, 1., 0.));
+        let out = albedo(&glass, &inside, up, 20000);
+        assert!(out.x() > 1.5 && out.x() < 2.3, "{:?}", out);
+    }
+
+    #[test]
+    fn albedo_is_continuous_in_transmission() {
+        // Grazing light, where the specular reflection is strongest
+        let r_in = Ray::new(point3(-3., 1., 0.), Vec3::new(3., -1., 0.));
+        let up = Vec3::new(0., 1., 0.);
+        let glass = |transmission| {
+            principled(
+                color(1., 1., 1.),
+                PrincipledParams {
+                    transmission,
+                    roughness: 0.3,
+                    ..PrincipledParams::default()
+                },
+            )
+        };
+        let n = 50000;
+        let albedos: Vec<Color> = [0.5, 0.999, 1.]
+            .iter()
+            .map(|&t| albedo(&glass(t), &r_in, up, n))
+            .collect();
+        for albedo in albedos.iter() {
+            assert!(albedo.max_component() <= 1., "{:?}", albedo);
+        }
+        assert!((albedos[1] - albedos[2]).length() < 0.01, "{:?}", albedos);
+    }
+}
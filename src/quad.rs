@@ -0,0 +1,354 @@
+use crate::aabb::{padded_box, AABB};
+use crate::hittable::{HitRecord, Hittable, SurfaceSample};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::{cross, dot, point3, unit_vector, Point3, Vec3};
+
+/// Parallelogram with a corner at `q` and edges `u` and `v`. Texture
+/// coordinates follow the edges, from 0 at `q` to 1 at the far corner.
+pub struct Quad<T>
+where
+    T: Material,
+{
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    /// Unit normal, `u` cross `v`
+    normal: Vec3,
+    /// Plane offset, `dot(normal, p)` for points of the plane
+    d: f64,
+    /// Projects a point of the plane onto the edges
+    w: Vec3,
+    area: f64,
+    material: T,
+}
+
+impl<T> Quad<T>
+where
+    T: Material,
+{
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: T) -> Quad<T> {
+        let n = cross(u, v);
+        let normal = unit_vector(&n);
+        Quad {
+            q,
+            u,
+            v,
+            normal,
+            d: dot(normal, q),
+            w: n / n.length_squared(),
+            area: n.length(),
+            material,
+        }
+    }
+
+    /// Rectangle in the plane `z = k`, facing +z.
+    pub fn xy_rect(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: T) -> Quad<T> {
+        let (u, v) = (Vec3::new(x1 - x0, 0., 0.), Vec3::new(0., y1 - y0, 0.));
+        Quad::new(point3(x0, y0, k), u, v, material)
+    }
+
+    /// Rectangle in the plane `y = k`, facing +y. The first texture coordinate
+    /// follows z and the second x.
+    pub fn xz_rect(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: T) -> Quad<T> {
+        let (u, v) = (Vec3::new(0., 0., z1 - z0), Vec3::new(x1 - x0, 0., 0.));
+        Quad::new(point3(x0, k, z0), u, v, material)
+    }
+
+    /// Rectangle in the plane `x = k`, facing +x.
+    pub fn yz_rect(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: T) -> Quad<T> {
+        let (u, v) = (Vec3::new(0., y1 - y0, 0.), Vec3::new(0., 0., z1 - z0));
+        Quad::new(point3(k, y0, z0), u, v, material)
+    }
+
+    /// Whether `p` is on the side the normal points to.
+    fn faces(&self, p: Point3) -> bool {
+        dot(self.normal, p) > self.d
+    }
+
+    /// Parameter of the ray on the quad and position on its edges.
+    #[inline]
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let denom = dot(self.normal, r.direction());
+        // Parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - dot(self.normal, r.origin())) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let planar = r.at(t) - self.q;
+        let alpha = dot(self.w, cross(planar, self.v));
+        let beta = dot(self.w, cross(self.u, planar));
+        if (0. ..=1.).contains(&alpha) && (0. ..=1.).contains(&beta) {
+            Some((t, alpha, beta))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T> Hittable for Quad<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, alpha, beta) = self.intersect(r, t_min, t_max)?;
+        let mut rec = HitRecord::new(r, r.at(t), self.normal, t, &self.material);
+        rec.u = alpha;
+        rec.v = beta;
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let corners = [self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let (mut min, mut max) = (self.q, self.q);
+        for c in corners.iter() {
+            for a in 0..3 {
+                min.e[a] = min[a].min(c[a]);
+                max.e[a] = max[a].max(c[a]);
+            }
+        }
+        Some(padded_box(min, max))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Uniformly sample the area of the quad.
+    fn sample(&self, origin: Point3, _time: f64) -> Option<SurfaceSample> {
+        let p = self.q + self.u * random_double() + self.v * random_double();
+        let to_p = p - origin;
+        let cosine = dot(unit_vector(&to_p), self.normal).abs();
+        if cosine < 1e-8 {
+            return None;
+        }
+        Some(SurfaceSample {
+            p,
+            pdf: to_p.length_squared() / (cosine * self.area),
+        })
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
+        match self.intersect(&Ray::new(origin, direction), 0.001, f64::INFINITY) {
+            Some((t, _, _)) => {
+                let cosine = dot(unit_vector(&direction), self.normal).abs();
+                (t * t * direction.length_squared()) / (cosine * self.area)
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// Axis-aligned box made of six quads facing outwards.
+pub struct BoxShape<T>
+where
+    T: Material,
+{
+    min: Point3,
+    max: Point3,
+    sides: Vec<Quad<T>>,
+}
+
+impl<T> BoxShape<T>
+where
+    T: Material + Clone,
+{
+    /// Box between two opposite corners, in any order.
+    pub fn new(a: Point3, b: Point3, material: T) -> BoxShape<T> {
+        let min = point3(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let max = point3(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+        let dx = Vec3::new(max.x() - min.x(), 0., 0.);
+        let dy = Vec3::new(0., max.y() - min.y(), 0.);
+        let dz = Vec3::new(0., 0., max.z() - min.z());
+        let quad = |q: Point3, u: Vec3, v: Vec3| Quad::new(q, u, v, material.clone());
+        let sides = vec![
+            quad(point3(min.x(), min.y(), max.z()), dx, dy),
+            quad(point3(max.x(), min.y(), max.z()), -dz, dy),
+            quad(point3(max.x(), min.y(), min.z()), -dx, dy),
+            quad(min, dz, dy),
+            quad(point3(min.x(), max.y(), max.z()), dx, -dz),
+            quad(min, dx, dz),
+        ];
+        BoxShape { min, max, sides }
+    }
+}
+
+impl<T> BoxShape<T>
+where
+    T: Material,
+{
+    fn facing_sides(&self, origin: Point3) -> impl Iterator<Item = &Quad<T>> {
+        self.sides.iter().filter(move |side| side.faces(origin))
+    }
+}
+
+impl<T> Hittable for BoxShape<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+        for side in self.sides.iter() {
+            if let Some(rec) = side.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some(rec);
+            }
+        }
+        closest
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(padded_box(self.min, self.max))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.sides[0].is_emissive()
+    }
+
+    /// Sample one of the sides facing `origin`, chosen uniformly. The box is
+    /// convex, so those sides are never hidden behind the others.
+    fn sample(&self, origin: Point3, time: f64) -> Option<SurfaceSample> {
+        let n = self.facing_sides(origin).count();
+        if n == 0 {
+            return None;
+        }
+        let idx = ((random_double() * n as f64) as usize).min(n - 1);
+        let side = self.facing_sides(origin).nth(idx)?;
+        side.sample(origin, time).map(|s| SurfaceSample {
+            pdf: s.pdf / n as f64,
+            ..s
+        })
+    }
+
+    /// Density of the side the ray enters through, the only one `sample`
+    /// could have produced in that direction.
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let r = Ray::new(origin, direction);
+        let first = self
+            .sides
+            .iter()
+            .filter_map(|side| Some((side.intersect(&r, 0.001, f64::INFINITY)?.0, side)))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        match first {
+            Some((_, side)) if side.faces(origin) => {
+                side.pdf_value(origin, direction, time) / self.facing_sides(origin).count() as f64
+            }
+            _ => 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::{color, random_unit_vector};
+    use std::f64::consts::PI;
+
+    fn gray() -> Lambertian {
+        Lambertian::new(color(0.5, 0.5, 0.5))
+    }
+
+    #[test]
+    fn hits_inside_edges_with_uvs() {
+        let quad = Quad::new(
+            point3(0., 0., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            gray(),
+        );
+        let r = Ray::new(point3(0.5, 0.25, 1.), Vec3::new(0., 0., -1.));
+        let rec = quad.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!((rec.t, rec.u, rec.v), (1., 0.25, 0.25));
+        assert!(rec.front_face);
+
+        let r = Ray::new(point3(2.5, 0.25, 1.), Vec3::new(0., 0., -1.));
+        assert!(quad.hit(&r, 0.001, f64::INFINITY).is_none());
+        let r = Ray::new(point3(0.5, 0.25, 1.), Vec3::new(1., 0., 0.));
+        assert!(quad.hit(&r, 0.001, f64::INFINITY).is_none());
+
+        // Flat along z, but still with some thickness for the BVH
+        let bbox = quad.bounding_box(0., 0.).unwrap();
+        assert!(bbox.max.z() > bbox.min.z());
+        let r = Ray::new(point3(-1., 0.5, 0.), Vec3::new(1., 0., 0.));
+        assert!(bbox.hit(r.origin(), r.inv_direction(), 0., 10.));
+    }
+
+    #[test]
+    fn rect_sample_pdf_matches_pdf_value() {
+        let rect = Quad::xz_rect(-1., 1., -1., 1., 3., gray());
+        let origin = point3(0.3, 0., -0.2);
+        for _ in 0..100 {
+            let sample = rect.sample(origin, 0.).unwrap();
+            let pdf = rect.pdf_value(origin, sample.p - origin, 0.);
+            assert!((pdf - sample.pdf).abs() < 1e-9 * pdf);
+        }
+        // The solid angle densities integrate to one over the directions
+        // hitting the rectangle
+        let n = 200000;
+        let mut total = 0.;
+        for _ in 0..n {
+            let mut d = random_unit_vector();
+            if d.y() < 0. {
+                d = -d;
+            }
+            total += rect.pdf_value(origin, d, 0.) * 2. * PI;
+        }
+        assert!((total / f64::from(n) - 1.).abs() < 0.02);
+    }
+
+    #[test]
+    fn box_faces_point_outwards() {
+        let shape = BoxShape::new(point3(1., 1., 1.), point3(-1., -1., -1.), gray());
+        let directions = [
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., 0., 1.),
+        ];
+        for d in directions.iter() {
+            for &d in [*d, -*d].iter() {
+                let r = Ray::new(d * 5., -d);
+                let rec = shape.hit(&r, 0.001, f64::INFINITY).unwrap();
+                assert!((rec.t - 4.).abs() < 1e-9);
+                assert!(rec.front_face);
+                assert!((rec.get_normal() - d).length() < 1e-9);
+            }
+        }
+        // From inside, the far side is hit from the back
+        let r = Ray::new(point3(0., 0., 0.), Vec3::new(0., 0., 1.));
+        assert!(!shape.hit(&r, 0.001, f64::INFINITY).unwrap().front_face);
+    }
+
+    #[test]
+    fn box_sample_pdf_matches_pdf_value() {
+        let shape = BoxShape::new(point3(-1., -1., -1.), point3(1., 0.5, 2.), gray());
+        let origin = point3(3., 2., -2.5);
+        for _ in 0..100 {
+            let sample = shape.sample(origin, 0.).unwrap();
+            let pdf = shape.pdf_value(origin, sample.p - origin, 0.);
+            assert!((pdf - sample.pdf).abs() < 1e-9 * pdf);
+        }
+        // Directions entering through each visible side are counted once.
+        // Half of the directions come from `sample`, which bounds the weights
+        // where faces are seen at grazing angles.
+        let n = 100000;
+        let mut total = 0.;
+        for i in 0..n {
+            let d = if i % 2 == 0 {
+                random_unit_vector()
+            } else {
+                shape.sample(origin, 0.).unwrap().p - origin
+            };
+            let pdf = shape.pdf_value(origin, d, 0.);
+            total += pdf / (0.5 / (4. * PI) + 0.5 * pdf);
+        }
+        assert!((total / f64::from(n) - 1.).abs() < 0.02);
+        // No side can be sampled from inside
+        assert!(shape.sample(point3(0., 0., 0.), 0.).is_none());
+    }
+}
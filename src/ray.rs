@@ -4,13 +4,24 @@ use crate::vec3::{Point3, Vec3};
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    /// Component-wise inverse of `dir`, computed once for the slab tests of
+    /// every box the ray meets
+    inv_dir: Vec3,
+    /// Instant the ray exists at, for moving objects
+    tm: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+        Ray::with_time(origin, direction, 0.)
+    }
+
+    pub fn with_time(origin: Vec3, direction: Vec3, time: f64) -> Ray {
         Ray {
             orig: origin,
             dir: direction,
+            inv_dir: Vec3::new(1. / direction.x(), 1. / direction.y(), 1. / direction.z()),
+            tm: time,
         }
     }
 
@@ -20,6 +31,13 @@ impl Ray {
     pub fn direction(&self) -> Vec3 {
         self.dir
     }
+    pub fn time(&self) -> f64 {
+        self.tm
+    }
+    /// Component-wise inverse of the direction, for slab tests.
+    pub fn inv_direction(&self) -> Vec3 {
+        self.inv_dir
+    }
 
     pub fn at(&self, t: f64) -> Vec3 {
         self.orig + self.dir * t
@@ -35,4 +53,13 @@ mod tests {
         let r = Ray::new(Vec3::new(-2., -2., -2.), Vec3::new(1., 1., 1.));
         assert_eq!(r.at(2.), Vec3::new(0., 0., 0.));
     }
+
+    #[test]
+    fn keeps_inverse_direction() {
+        let r = Ray::new(Vec3::new(0., 0., 0.), Vec3::new(2., -0.5, 0.));
+        assert_eq!(r.inv_direction().x(), 0.5);
+        assert_eq!(r.inv_direction().y(), -2.);
+        // Slab tests rely on infinities for axis-parallel rays
+        assert_eq!(r.inv_direction().z(), f64::INFINITY);
+    }
 }
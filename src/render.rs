@@ -0,0 +1,189 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::background::Background;
+use crate::bvh::LinearBVH;
+use crate::camera::Camera;
+use crate::image::Framebuffer;
+use crate::integrator::ray_color;
+use crate::light::Lights;
+use crate::utils::{random_double, seed_rng};
+use crate::vec3::{color, Color};
+
+/// Side of the square tiles handed out to the render threads.
+const TILE_SIZE: usize = 16;
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: LinearBVH,
+    pub lights: Lights,
+    pub background: Background,
+    pub im_height: usize,
+    pub im_width: usize,
+    pub max_depth: i32,
+}
+
+/// A rectangle of pixels, with rows counted from the top of the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Tile {
+    x0: usize,
+    y0: usize,
+    width: usize,
+    height: usize,
+}
+
+fn tiles(im_width: usize, im_height: usize) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for y0 in (0..im_height).step_by(TILE_SIZE) {
+        for x0 in (0..im_width).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                width: TILE_SIZE.min(im_width - x0),
+                height: TILE_SIZE.min(im_height - y0),
+            });
+        }
+    }
+    tiles
+}
+
+/// Render the scene with `samples` samples per pixel.
+///
+/// The image is cut into tiles that `n_threads` workers pull from a shared
+/// queue until it is empty, so fast threads pick up the slack of slow ones.
+/// With a seed, each tile gets its own random stream and the image does not
+/// depend on the number of threads.
+pub fn render(scene: &Scene, samples: u32, n_threads: usize, seed: Option<u64>) -> Framebuffer {
+    let tiles = tiles(scene.im_width, scene.im_height);
+    let next_tile = AtomicUsize::new(0);
+    let framebuffer = Mutex::new(Framebuffer::new(scene.im_width, scene.im_height));
+
+    thread::scope(|s| {
+        for _ in 0..n_threads.max(1) {
+            s.spawn(|| loop {
+                let idx = next_tile.fetch_add(1, Ordering::Relaxed);
+                let tile = match tiles.get(idx) {
+                    Some(tile) => tile,
+                    None => break,
+                };
+                eprint!("\rTiles remaining: {} ", tiles.len() - idx - 1);
+                if let Some(seed) = seed {
+                    seed_rng(seed ^ (idx as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                }
+                let pixels = render_tile(scene, tile, samples);
+                let mut framebuffer = framebuffer.lock().unwrap();
+                for (k, c) in pixels.into_iter().enumerate() {
+                    framebuffer.set(tile.x0 + k % tile.width, tile.y0 + k / tile.width, c);
+                }
+            });
+        }
+    });
+    eprintln!();
+    framebuffer.into_inner().unwrap()
+}
+
+/// Average radiance of the pixels of a tile, row by row.
+fn render_tile(scene: &Scene, tile: &Tile, samples: u32) -> Vec<Color> {
+    let scale = 1. / f64::from(samples);
+    let mut pixels = Vec::with_capacity(tile.width * tile.height);
+    for y in tile.y0..tile.y0 + tile.height {
+        for x in tile.x0..tile.x0 + tile.width {
+            let mut pixel_color = color(0., 0., 0.);
+            for _ in 0..samples {
+                pixel_color += sample_pixel(scene, x, y);
+            }
+            pixels.push(pixel_color * scale);
+        }
+    }
+    pixels
+}
+
+#[inline]
+fn sample_pixel(scene: &Scene, x: usize, y: usize) -> Color {
+    let row = scene.im_height - 1 - y;
+    let u = (x as f64 + random_double()) / scene.im_width as f64;
+    let v = (row as f64 + random_double()) / scene.im_height as f64;
+    let ray = scene.camera.get_ray(u, v);
+    ray_color(
+        &ray,
+        &scene.world,
+        &scene.lights,
+        &scene.background,
+        scene.max_depth,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::hittable::{BoxedHittable, Sphere};
+    use crate::material::Lambertian;
+    use crate::vec3::{point3, Vec3};
+
+    #[test]
+    fn tiles_cover_image_once() {
+        let (w, h) = (37, 20);
+        let mut covered = vec![0; w * h];
+        for tile in tiles(w, h) {
+            for y in tile.y0..tile.y0 + tile.height {
+                for x in tile.x0..tile.x0 + tile.width {
+                    covered[y * w + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&n| n == 1));
+    }
+
+    /// A diffuse sphere lit by the background, so pixels are noisy.
+    fn sphere_scene(im_width: usize, im_height: usize) -> Scene {
+        let objects: Vec<BoxedHittable> = vec![Box::new(Sphere::new(
+            point3(0., 0., -1.),
+            0.5,
+            Lambertian::new(color(0.5, 0.5, 0.5)),
+        ))];
+        let camera = CameraSettings {
+            lookfrom: point3(0., 0., 0.),
+            lookat: point3(0., 0., -1.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 60.,
+            aperture: 0.,
+            focus_dist: 1.,
+            time0: 0.,
+            time1: 0.,
+        };
+        Scene {
+            camera: camera.build(im_width as f64 / im_height as f64),
+            world: LinearBVH::new(objects, 0., 0.),
+            lights: Lights::extract(&mut vec![]),
+            background: Background::Constant(color(1., 1., 1.)),
+            im_height,
+            im_width,
+            max_depth: 4,
+        }
+    }
+
+    #[test]
+    fn seeded_render_does_not_depend_on_threads() {
+        let scene = sphere_scene(20, 10);
+        let a = render(&scene, 3, 1, Some(7));
+        let b = render(&scene, 3, 4, Some(7));
+        for y in 0..10 {
+            for x in 0..20 {
+                assert_eq!(a.get(x, y), b.get(x, y));
+            }
+        }
+        // Corners only see the background
+        assert_eq!(a.get(0, 0), color(1., 1., 1.));
+    }
+
+    #[test]
+    fn single_pixel_covers_the_whole_frame() {
+        let image = render(&sphere_scene(1, 1), 64, 1, Some(3));
+        let pixel = image.get(0, 0);
+        assert!((0..3).all(|c| pixel[c].is_finite()));
+        // Both the sphere and the background are in view
+        assert!(pixel.x() > 0.3 && pixel.x() < 0.95);
+    }
+}
@@ -0,0 +1,1689 @@
+use crate::aabb::AABB;
+use crate::background::Background;
+use crate::bvh::DEFAULT_LEAF_SIZE;
+use crate::camera::CameraSettings;
+use crate::csg::{Csg, CsgOp};
+use crate::hittable::{BoxedHittable, HittableList, MovingSphere, Sphere};
+use crate::instance::{Instance, Keyframe};
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::medium::{ConstantMedium, GridMedium, VoxelGrid};
+use crate::microfacet::{metal_ior, Conductor, RoughDielectric};
+use crate::obj::load_obj;
+use crate::principled::{Principled, PrincipledParams};
+use crate::quad::{BoxShape, Quad};
+use crate::sdf::{
+    BoxSdf, Repeat, SdfShape, SharedSdf, SmoothUnion, SphereSdf, TorusSdf, Translate, Twist,
+    DEFAULT_EPSILON,
+};
+use crate::shapes::{Cone, Cylinder, Disk, Plane, Torus};
+use crate::texture::{
+    CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, SharedTexture, SolidColor,
+};
+use crate::triangle::{Triangle, TriangleMesh};
+use crate::vec3::{Point3, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use toml::de::{DeTable, DeValue};
+use toml::Spanned;
+
+pub type SharedMaterial = Arc<dyn Material + Send + Sync>;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RenderSettings {
+    pub width: i32,
+    pub height: i32,
+    pub samples: i32,
+    pub max_depth: i32,
+    /// Maximum number of objects in a leaf of the scene BVH
+    pub bvh_leaf_size: i32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 1200,
+            height: 675,
+            samples: 500,
+            max_depth: 100,
+            bvh_leaf_size: DEFAULT_LEAF_SIZE as i32,
+        }
+    }
+}
+
+/// Everything needed to render a scene, before acceleration structures are built.
+pub struct SceneDescription {
+    pub world: HittableList,
+    pub camera: CameraSettings,
+    pub background: Background,
+    pub settings: RenderSettings,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse {
+        file: String,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            SceneError::Parse {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+/// Load a scene file.
+///
+/// Scene files are TOML documents with `[camera]`, `[render]` and `[background]`
+/// tables, plus any number of `[[texture]]`, `[[material]]`, `[[sdf]]` and
+/// `[[object]]` tables. Values are numbers, booleans, strings and arrays.
+/// Relative paths are resolved from the directory of the scene file.
+pub fn load_scene(path: &Path) -> Result<SceneDescription, SceneError> {
+    let text = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&text, base_dir).map_err(|LineError { line, message }| SceneError::Parse {
+        file: path.display().to_string(),
+        line,
+        message,
+    })
+}
+
+#[derive(Debug)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+fn line_error<T>(line: usize, message: String) -> Result<T, LineError> {
+    Err(LineError { line, message })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    Array(Vec<Value>),
+}
+
+struct Entry {
+    key: String,
+    value: Value,
+    line: usize,
+}
+
+struct Table {
+    name: String,
+    line: usize,
+    is_array: bool,
+    entries: Vec<Entry>,
+}
+
+impl Table {
+    fn header(&self) -> String {
+        if self.is_array {
+            format!("[[{}]]", self.name)
+        } else {
+            format!("[{}]", self.name)
+        }
+    }
+
+    fn get<T>(
+        &mut self,
+        key: &str,
+        convert: impl Fn(&Entry) -> Result<T, LineError>,
+    ) -> Result<Option<T>, LineError> {
+        match self.entries.iter().position(|e| e.key == key) {
+            Some(idx) => convert(&self.entries.remove(idx)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn require<T>(
+        &mut self,
+        key: &str,
+        convert: impl Fn(&Entry) -> Result<T, LineError>,
+    ) -> Result<T, LineError> {
+        match self.get(key, convert)? {
+            Some(value) => Ok(value),
+            None => line_error(self.line, format!("{} is missing '{}'", self.header(), key)),
+        }
+    }
+
+    /// Line of the entry for `key`, or of the table header.
+    fn line_of(&self, key: &str) -> usize {
+        self.entries
+            .iter()
+            .find(|e| e.key == key)
+            .map_or(self.line, |e| e.line)
+    }
+
+    /// Fail on keys that were not consumed, which are most likely typos.
+    fn finish(self) -> Result<(), LineError> {
+        match self.entries.first() {
+            Some(entry) => line_error(
+                entry.line,
+                format!("unknown key '{}' in {}", entry.key, self.header()),
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+fn to_number(entry: &Entry) -> Result<f64, LineError> {
+    match entry.value {
+        Value::Number(n) => Ok(n),
+        _ => line_error(entry.line, format!("'{}' must be a number", entry.key)),
+    }
+}
+
+fn to_count(entry: &Entry) -> Result<i32, LineError> {
+    match entry.value {
+        Value::Number(n) if n >= 1. && n.fract() == 0. && n <= f64::from(i32::MAX) => Ok(n as i32),
+        _ => line_error(
+            entry.line,
+            format!("'{}' must be a positive integer", entry.key),
+        ),
+    }
+}
+
+fn to_fraction(entry: &Entry) -> Result<f64, LineError> {
+    match entry.value {
+        Value::Number(n) if (0. ..=1.).contains(&n) => Ok(n),
+        _ => line_error(
+            entry.line,
+            format!("'{}' must be a number between 0 and 1", entry.key),
+        ),
+    }
+}
+
+fn to_seed(entry: &Entry) -> Result<u64, LineError> {
+    match entry.value {
+        Value::Number(n) if n >= 0. && n.fract() == 0. && n < u64::MAX as f64 => Ok(n as u64),
+        _ => line_error(
+            entry.line,
+            format!("'{}' must be a non-negative integer", entry.key),
+        ),
+    }
+}
+
+fn to_bool(entry: &Entry) -> Result<bool, LineError> {
+    match entry.value {
+        Value::Bool(b) => Ok(b),
+        _ => line_error(entry.line, format!("'{}' must be true or false", entry.key)),
+    }
+}
+
+fn to_string(entry: &Entry) -> Result<String, LineError> {
+    match &entry.value {
+        Value::Str(s) => Ok(s.clone()),
+        _ => line_error(entry.line, format!("'{}' must be a string", entry.key)),
+    }
+}
+
+fn value_to_vec3(value: &Value) -> Option<Vec3> {
+    match value {
+        Value::Array(items) if items.len() == 3 => {
+            let mut e = [0.; 3];
+            for (slot, item) in e.iter_mut().zip(items) {
+                match item {
+                    Value::Number(n) => *slot = *n,
+                    _ => return None,
+                }
+            }
+            Some(Vec3 { e })
+        }
+        _ => None,
+    }
+}
+
+fn to_vec3(entry: &Entry) -> Result<Vec3, LineError> {
+    value_to_vec3(&entry.value).map_or_else(
+        || {
+            line_error(
+                entry.line,
+                format!("'{}' must be an array of 3 numbers", entry.key),
+            )
+        },
+        Ok,
+    )
+}
+
+/// A vector that can be normalized, such as an axis.
+fn to_direction(entry: &Entry) -> Result<Vec3, LineError> {
+    match to_vec3(entry)? {
+        v if v.length_squared() > 0. => Ok(v),
+        _ => line_error(entry.line, format!("'{}' must not be zero", entry.key)),
+    }
+}
+
+fn to_vec3_triple(entry: &Entry) -> Result<[Vec3; 3], LineError> {
+    let error = || {
+        line_error(
+            entry.line,
+            format!("'{}' must be an array of 3 points", entry.key),
+        )
+    };
+    match &entry.value {
+        Value::Array(items) if items.len() == 3 => {
+            match (
+                value_to_vec3(&items[0]),
+                value_to_vec3(&items[1]),
+                value_to_vec3(&items[2]),
+            ) {
+                (Some(a), Some(b), Some(c)) => Ok([a, b, c]),
+                _ => error(),
+            }
+        }
+        _ => error(),
+    }
+}
+
+/// Maps byte offsets in a document to 1-based line numbers.
+struct LineIndex {
+    newlines: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> LineIndex {
+        LineIndex {
+            newlines: text.match_indices('\n').map(|(idx, _)| idx).collect(),
+        }
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.newlines.partition_point(|&idx| idx < offset) + 1
+    }
+}
+
+fn to_value(key: &str, value: &Spanned<DeValue>, lines: &LineIndex) -> Result<Value, LineError> {
+    let line = lines.line(value.span().start);
+    match value.get_ref() {
+        DeValue::String(s) => Ok(Value::Str(s.to_string())),
+        DeValue::Integer(n) => match i64::from_str_radix(n.as_str(), n.radix()) {
+            Ok(n) => Ok(Value::Number(n as f64)),
+            Err(_) => line_error(line, format!("'{}' is out of range", key)),
+        },
+        DeValue::Float(x) => match x.as_str().parse() {
+            Ok(x) => Ok(Value::Number(x)),
+            Err(_) => line_error(line, format!("'{}' is out of range", key)),
+        },
+        DeValue::Boolean(b) => Ok(Value::Bool(*b)),
+        DeValue::Array(items) => items
+            .iter()
+            .map(|item| to_value(key, item, lines))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        DeValue::Table(_) => line_error(line, format!("'{}' must not be a table", key)),
+        DeValue::Datetime(_) => line_error(line, format!("'{}' must not be a date", key)),
+    }
+}
+
+fn to_table(
+    name: &str,
+    table: &Spanned<DeValue>,
+    is_array: bool,
+    lines: &LineIndex,
+) -> Result<Table, LineError> {
+    let line = lines.line(table.span().start);
+    let items = match table.get_ref() {
+        DeValue::Table(items) => items,
+        _ => return line_error(line, format!("key '{}' outside of any table", name)),
+    };
+    // Keep entries in file order so errors point at the first offending key
+    let mut items: Vec<_> = items.iter().collect();
+    items.sort_by_key(|(key, _)| key.span().start);
+    let entries = items
+        .into_iter()
+        .map(|(key, value)| {
+            Ok(Entry {
+                key: key.get_ref().to_string(),
+                value: to_value(key.get_ref(), value, lines)?,
+                line: lines.line(key.span().start),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Table {
+        name: name.to_string(),
+        line,
+        is_array,
+        entries,
+    })
+}
+
+/// Split a TOML document into its top-level tables, in file order.
+///
+/// `[name]` and `name = { ... }` give a table, `[[name]]` and
+/// `name = [{ ... }, ...]` give one array table per element.
+fn parse_document(text: &str) -> Result<Vec<Table>, LineError> {
+    let lines = LineIndex::new(text);
+    let document = DeTable::parse(text).map_err(|err| LineError {
+        line: err.span().map_or(1, |span| lines.line(span.start)),
+        message: err.message().to_string(),
+    })?;
+    let mut tables = vec![];
+    for (name, value) in document.get_ref() {
+        match value.get_ref() {
+            DeValue::Array(items) => {
+                for item in items {
+                    tables.push(to_table(name.get_ref(), item, true, &lines)?);
+                }
+            }
+            DeValue::Table(_) => tables.push(to_table(name.get_ref(), value, false, &lines)?),
+            _ => {
+                return line_error(
+                    lines.line(name.span().start),
+                    format!("key '{}' outside of any table", name.get_ref()),
+                )
+            }
+        }
+    }
+    tables.sort_by_key(|table| table.line);
+    Ok(tables)
+}
+
+/// Build a scene from the text of a scene file.
+pub fn parse_scene(text: &str, base_dir: &Path) -> Result<SceneDescription, LineError> {
+    let tables = parse_document(text)?;
+    let (texture_tables, tables): (Vec<Table>, Vec<Table>) =
+        tables.into_iter().partition(|t| t.name == "texture");
+    let (material_tables, tables): (Vec<Table>, Vec<Table>) =
+        tables.into_iter().partition(|t| t.name == "material");
+    let (sdf_tables, tables): (Vec<Table>, Vec<Table>) =
+        tables.into_iter().partition(|t| t.name == "sdf");
+
+    // Textures may only refer to textures defined above them
+    let mut textures: HashMap<String, SharedTexture> = HashMap::new();
+    for mut table in texture_tables {
+        if !table.is_array {
+            return line_error(table.line, "textures are declared with [[texture]]".into());
+        }
+        let name_line = table.line_of("name");
+        let name = table.require("name", to_string)?;
+        if textures.contains_key(&name) {
+            return line_error(name_line, format!("duplicate texture '{}'", name));
+        }
+        let texture = build_texture(&mut table, &textures, base_dir)?;
+        table.finish()?;
+        textures.insert(name, texture);
+    }
+
+    // Materials first, so objects may refer to materials defined further down
+    let mut materials: HashMap<String, SharedMaterial> = HashMap::new();
+    for mut table in material_tables {
+        if !table.is_array {
+            return line_error(
+                table.line,
+                "materials are declared with [[material]]".into(),
+            );
+        }
+        let name_line = table.line_of("name");
+        let name = table.require("name", to_string)?;
+        if materials.contains_key(&name) {
+            return line_error(name_line, format!("duplicate material '{}'", name));
+        }
+        let material = build_material(&mut table, &textures)?;
+        table.finish()?;
+        materials.insert(name, material);
+    }
+
+    // Distance functions may only refer to distance functions defined above them
+    let mut sdfs: HashMap<String, SharedSdf> = HashMap::new();
+    for mut table in sdf_tables {
+        if !table.is_array {
+            return line_error(
+                table.line,
+                "distance functions are declared with [[sdf]]".into(),
+            );
+        }
+        let name_line = table.line_of("name");
+        let name = table.require("name", to_string)?;
+        if sdfs.contains_key(&name) {
+            return line_error(name_line, format!("duplicate sdf '{}'", name));
+        }
+        let sdf = build_sdf(&mut table, &sdfs)?;
+        table.finish()?;
+        sdfs.insert(name, sdf);
+    }
+
+    let mut camera = None;
+    let mut settings = RenderSettings::default();
+    let mut background = Background::sky();
+    let mut objects = ObjectList {
+        objects: vec![],
+        names: HashMap::new(),
+    };
+    // Meshes used several times are loaded once and shared
+    let mut meshes: HashMap<PathBuf, Arc<TriangleMesh>> = HashMap::new();
+    for mut table in tables {
+        match (table.name.as_str(), table.is_array) {
+            ("camera", false) => camera = Some(build_camera(&mut table)?),
+            ("render", false) => settings = build_render_settings(&mut table)?,
+            ("background", false) => background = build_background(&mut table)?,
+            ("object", true) => {
+                let name_line = table.line_of("name");
+                let name = table.get("name", to_string)?;
+                if let Some(name) = &name {
+                    if objects.names.contains_key(name) {
+                        return line_error(name_line, format!("duplicate object '{}'", name));
+                    }
+                }
+                let object = build_object(
+                    &mut table,
+                    &materials,
+                    &sdfs,
+                    &mut objects,
+                    &mut meshes,
+                    base_dir,
+                )?;
+                if let Some(name) = name {
+                    objects.names.insert(name, objects.objects.len());
+                }
+                objects.objects.push(Some(object));
+            }
+            _ => return line_error(table.line, format!("unknown table {}", table.header())),
+        }
+        table.finish()?;
+    }
+
+    let end = text.lines().count();
+    let camera = match camera {
+        Some(camera) => camera,
+        None => return line_error(end, "missing [camera] table".into()),
+    };
+    let objects: Vec<BoxedHittable> = objects.objects.into_iter().flatten().collect();
+    if objects.is_empty() {
+        return line_error(end, "scene has no [[object]]".into());
+    }
+    Ok(SceneDescription {
+        world: HittableList::new(objects),
+        camera,
+        background,
+        settings,
+    })
+}
+
+fn build_camera(table: &mut Table) -> Result<CameraSettings, LineError> {
+    let lookfrom: Point3 = table.require("lookfrom", to_vec3)?;
+    let lookat: Point3 = table.require("lookat", to_vec3)?;
+    let time1_line = table.line_of("time1");
+    let time0 = table.get("time0", to_number)?.unwrap_or(0.);
+    let time1 = table.get("time1", to_number)?.unwrap_or(time0);
+    if time1 < time0 {
+        return line_error(time1_line, "'time1' must not be before 'time0'".into());
+    }
+    Ok(CameraSettings {
+        lookfrom,
+        lookat,
+        vup: table
+            .get("vup", to_vec3)?
+            .unwrap_or_else(|| Vec3::new(0., 1., 0.)),
+        vfov: table.get("vfov", to_number)?.unwrap_or(40.),
+        aperture: table.get("aperture", to_number)?.unwrap_or(0.),
+        focus_dist: table
+            .get("focus_dist", to_number)?
+            .unwrap_or_else(|| (lookfrom - lookat).length()),
+        time0,
+        time1,
+    })
+}
+
+fn build_render_settings(table: &mut Table) -> Result<RenderSettings, LineError> {
+    let defaults = RenderSettings::default();
+    Ok(RenderSettings {
+        width: table.get("width", to_count)?.unwrap_or(defaults.width),
+        height: table.get("height", to_count)?.unwrap_or(defaults.height),
+        samples: table.get("samples", to_count)?.unwrap_or(defaults.samples),
+        max_depth: table
+            .get("max_depth", to_count)?
+            .unwrap_or(defaults.max_depth),
+        bvh_leaf_size: table
+            .get("bvh_leaf_size", to_count)?
+            .unwrap_or(defaults.bvh_leaf_size),
+    })
+}
+
+fn build_background(table: &mut Table) -> Result<Background, LineError> {
+    let type_line = table.line_of("type");
+    match table.require("type", to_string)?.as_str() {
+        "sky" => Ok(Background::sky()),
+        "none" => Ok(Background::None),
+        "constant" => Ok(Background::Constant(table.require("color", to_vec3)?)),
+        "gradient" => Ok(Background::Gradient {
+            bottom: table.require("bottom", to_vec3)?,
+            top: table.require("top", to_vec3)?,
+        }),
+        other => line_error(type_line, format!("unknown background type '{}'", other)),
+    }
+}
+
+/// A texture given either inline as a color or by name.
+fn to_texture(
+    entry: &Entry,
+    textures: &HashMap<String, SharedTexture>,
+) -> Result<SharedTexture, LineError> {
+    match &entry.value {
+        Value::Str(name) => match textures.get(name) {
+            Some(texture) => Ok(Arc::clone(texture)),
+            None => line_error(entry.line, format!("unknown texture '{}'", name)),
+        },
+        value => match value_to_vec3(value) {
+            Some(c) => Ok(Arc::new(SolidColor::new(c))),
+            None => line_error(
+                entry.line,
+                format!("'{}' must be a color or the name of a texture", entry.key),
+            ),
+        },
+    }
+}
+
+fn build_texture(
+    table: &mut Table,
+    textures: &HashMap<String, SharedTexture>,
+    base_dir: &Path,
+) -> Result<SharedTexture, LineError> {
+    let type_line = table.line_of("type");
+    let texture: SharedTexture = match table.require("type", to_string)?.as_str() {
+        "solid" => Arc::new(SolidColor::new(table.require("color", to_vec3)?)),
+        "checker" => Arc::new(CheckerTexture::new(
+            table.require("odd", |e| to_texture(e, textures))?,
+            table.require("even", |e| to_texture(e, textures))?,
+            table.get("scale", to_number)?.unwrap_or(1.),
+        )),
+        kind @ ("noise" | "turbulence" | "marble" | "wood") => {
+            let pattern = match kind {
+                "noise" => NoisePattern::Noise,
+                "turbulence" => NoisePattern::Turbulence,
+                "marble" => NoisePattern::Marble,
+                _ => NoisePattern::Wood,
+            };
+            let texture = NoiseTexture::new(
+                pattern,
+                table.get("scale", to_number)?.unwrap_or(1.),
+                table.get("octaves", to_count)?.unwrap_or(7) as u32,
+                table.get("seed", to_seed)?.unwrap_or(0),
+            );
+            let colors_line = table.line_of("low").max(table.line_of("high"));
+            match (table.get("low", to_vec3)?, table.get("high", to_vec3)?) {
+                (Some(low), Some(high)) => Arc::new(texture.with_colors(low, high)),
+                (None, None) => Arc::new(texture),
+                _ => {
+                    return line_error(
+                        colors_line,
+                        "'low' and 'high' must be given together".into(),
+                    )
+                }
+            }
+        }
+        "image" => {
+            let line = table.line_of("path");
+            let path = base_dir.join(table.require("path", to_string)?);
+            match ImageTexture::load(&path) {
+                Ok(image) => Arc::new(image),
+                Err(err) => {
+                    return line_error(line, format!("could not load {}: {}", path.display(), err))
+                }
+            }
+        }
+        other => return line_error(type_line, format!("unknown texture type '{}'", other)),
+    };
+    Ok(texture)
+}
+
+fn to_sdf(entry: &Entry, sdfs: &HashMap<String, SharedSdf>) -> Result<SharedSdf, LineError> {
+    let name = to_string(entry)?;
+    match sdfs.get(&name) {
+        Some(sdf) => Ok(Arc::clone(sdf)),
+        None => line_error(entry.line, format!("unknown sdf '{}'", name)),
+    }
+}
+
+fn build_sdf(table: &mut Table, sdfs: &HashMap<String, SharedSdf>) -> Result<SharedSdf, LineError> {
+    let type_line = table.line_of("type");
+    let sdf: SharedSdf = match table.require("type", to_string)?.as_str() {
+        "sphere" => Arc::new(SphereSdf::new(table.require("radius", to_number)?)),
+        "box" => Arc::new(BoxSdf::new(table.require("size", to_vec3)? / 2.)),
+        "round_box" => Arc::new(BoxSdf::rounded(
+            table.require("size", to_vec3)? / 2.,
+            table.require("radius", to_number)?,
+        )),
+        "torus" => Arc::new(TorusSdf::new(
+            table.require("major_radius", to_number)?,
+            table.require("minor_radius", to_number)?,
+        )),
+        "smooth_union" => Arc::new(SmoothUnion::new(
+            table.require("left", |e| to_sdf(e, sdfs))?,
+            table.require("right", |e| to_sdf(e, sdfs))?,
+            table.require("smoothness", to_number)?,
+        )),
+        "repeat" => Arc::new(Repeat::new(
+            table.require("sdf", |e| to_sdf(e, sdfs))?,
+            table.require("period", to_vec3)?,
+        )),
+        "twist" => Arc::new(Twist::new(
+            table.require("sdf", |e| to_sdf(e, sdfs))?,
+            table.require("rate", to_number)?.to_radians(),
+        )),
+        other => return line_error(type_line, format!("unknown sdf type '{}'", other)),
+    };
+    Ok(match table.get("center", to_vec3)? {
+        Some(center) => Arc::new(Translate::new(sdf, center)),
+        None => sdf,
+    })
+}
+
+fn build_material(
+    table: &mut Table,
+    textures: &HashMap<String, SharedTexture>,
+) -> Result<SharedMaterial, LineError> {
+    let type_line = table.line_of("type");
+    let material: SharedMaterial = match table.require("type", to_string)?.as_str() {
+        "lambertian" => Arc::new(Lambertian::textured(
+            table.require("color", |e| to_texture(e, textures))?,
+        )),
+        "metal" => Arc::new(Metal::textured(
+            table.require("color", |e| to_texture(e, textures))?,
+            table.get("fuzz", to_number)?.unwrap_or(0.),
+        )),
+        "dielectric" => Arc::new(Dielectric::new(table.require("ior", to_number)?)),
+        "conductor" => {
+            let metal_line = table.line_of("metal");
+            let (eta, k) = match table.get("metal", to_string)? {
+                Some(name) => match metal_ior(&name) {
+                    Some(ior) => ior,
+                    None => return line_error(metal_line, format!("unknown metal '{}'", name)),
+                },
+                None => (table.require("eta", to_vec3)?, table.require("k", to_vec3)?),
+            };
+            let roughness = table.get("roughness", to_fraction)?.unwrap_or(0.);
+            Arc::new(Conductor::new(eta, k, roughness))
+        }
+        "rough_dielectric" => Arc::new(RoughDielectric::new(
+            table.require("ior", to_number)?,
+            table.require("roughness", to_fraction)?,
+        )),
+        "principled" => {
+            let defaults = PrincipledParams::default();
+            let params = PrincipledParams {
+                base_color: match table.get("color", |e| to_texture(e, textures))? {
+                    Some(texture) => texture,
+                    None => defaults.base_color,
+                },
+                metallic: table
+                    .get("metallic", to_fraction)?
+                    .unwrap_or(defaults.metallic),
+                roughness: table
+                    .get("roughness", to_fraction)?
+                    .unwrap_or(defaults.roughness),
+                specular: table
+                    .get("specular", to_fraction)?
+                    .unwrap_or(defaults.specular),
+                clearcoat: table
+                    .get("clearcoat", to_fraction)?
+                    .unwrap_or(defaults.clearcoat),
+                sheen: table.get("sheen", to_fraction)?.unwrap_or(defaults.sheen),
+                transmission: table
+                    .get("transmission", to_fraction)?
+                    .unwrap_or(defaults.transmission),
+                ior: table.get("ior", to_number)?.unwrap_or(defaults.ior),
+            };
+            Arc::new(Principled::new(params))
+        }
+        "diffuse_light" => match table.get("intensity", to_number)? {
+            Some(intensity) => {
+                let emit = table.require("color", to_vec3)?;
+                Arc::new(DiffuseLight::new(emit * intensity))
+            }
+            None => Arc::new(DiffuseLight::textured(
+                table.require("color", |e| to_texture(e, textures))?,
+            )),
+        },
+        "isotropic" => Arc::new(Isotropic::textured(
+            table.require("color", |e| to_texture(e, textures))?,
+        )),
+        other => return line_error(type_line, format!("unknown material type '{}'", other)),
+    };
+    Ok(material)
+}
+
+fn lookup_material(
+    table: &mut Table,
+    materials: &HashMap<String, SharedMaterial>,
+) -> Result<SharedMaterial, LineError> {
+    let line = table.line_of("material");
+    let name = table.require("material", to_string)?;
+    match materials.get(&name) {
+        Some(material) => Ok(Arc::clone(material)),
+        None => line_error(line, format!("unknown material '{}'", name)),
+    }
+}
+
+/// Objects built so far. Named objects may be taken by `csg` objects further
+/// down, and are then only rendered as part of them.
+struct ObjectList {
+    objects: Vec<Option<BoxedHittable>>,
+    names: HashMap<String, usize>,
+}
+
+impl ObjectList {
+    /// Take the object named by `key` out of the list.
+    fn take(&mut self, table: &mut Table, key: &str) -> Result<BoxedHittable, LineError> {
+        let line = table.line_of(key);
+        let name = table.require(key, to_string)?;
+        match self.names.get(&name) {
+            Some(&idx) => match self.objects[idx].take() {
+                Some(object) => Ok(object),
+                None => line_error(line, format!("object '{}' is already used", name)),
+            },
+            None => line_error(line, format!("unknown object '{}'", name)),
+        }
+    }
+}
+
+fn build_object(
+    table: &mut Table,
+    materials: &HashMap<String, SharedMaterial>,
+    sdfs: &HashMap<String, SharedSdf>,
+    objects: &mut ObjectList,
+    meshes: &mut HashMap<PathBuf, Arc<TriangleMesh>>,
+    base_dir: &Path,
+) -> Result<BoxedHittable, LineError> {
+    let type_line = table.line_of("type");
+    let type_name = table.require("type", to_string)?;
+    let object: BoxedHittable = match type_name.as_str() {
+        "sphere" => {
+            let center = table.require("center", to_vec3)?;
+            let radius = table.require("radius", to_number)?;
+            let material = lookup_material(table, materials)?;
+            match table.get("center1", to_vec3)? {
+                // Moves from `center` at `time0` to `center1` at `time1`
+                Some(center1) => {
+                    let time1_line = table.line_of("time1");
+                    let time0 = table.get("time0", to_number)?.unwrap_or(0.);
+                    let time1 = table.get("time1", to_number)?.unwrap_or(1.);
+                    if time1 <= time0 {
+                        return line_error(time1_line, "'time1' must be after 'time0'".into());
+                    }
+                    Box::new(MovingSphere::new(
+                        center, center1, time0, time1, radius, material,
+                    ))
+                }
+                None => Box::new(Sphere::new(center, radius, material)),
+            }
+        }
+        "triangle" => {
+            let [v0, v1, v2] = table.require("vertices", to_vec3_triple)?;
+            let normals = table.get("normals", to_vec3_triple)?;
+            let material = lookup_material(table, materials)?;
+            match normals {
+                Some(normals) => Box::new(Triangle::with_normals(v0, v1, v2, normals, material)),
+                None => Box::new(Triangle::new(v0, v1, v2, material)),
+            }
+        }
+        "quad" => Box::new(Quad::new(
+            table.require("corner", to_vec3)?,
+            table.require("u", to_vec3)?,
+            table.require("v", to_vec3)?,
+            lookup_material(table, materials)?,
+        )),
+        "box" => Box::new(BoxShape::new(
+            table.require("min", to_vec3)?,
+            table.require("max", to_vec3)?,
+            lookup_material(table, materials)?,
+        )),
+        "disk" => Box::new(Disk::new(
+            table.require("center", to_vec3)?,
+            table.require("normal", to_direction)?,
+            table.require("radius", to_number)?,
+            lookup_material(table, materials)?,
+        )),
+        "plane" => Box::new(Plane::new(
+            table.require("point", to_vec3)?,
+            table.require("normal", to_direction)?,
+            lookup_material(table, materials)?,
+        )),
+        "cylinder" | "cone" => {
+            let end = if type_name == "cylinder" {
+                "top"
+            } else {
+                "apex"
+            };
+            let end_line = table.line_of(end);
+            let base = table.require("base", to_vec3)?;
+            let end_point = table.require(end, to_vec3)?;
+            if end_point == base {
+                return line_error(end_line, format!("'{}' must differ from 'base'", end));
+            }
+            let radius = table.require("radius", to_number)?;
+            let capped = table.get("capped", to_bool)?.unwrap_or(true);
+            let material = lookup_material(table, materials)?;
+            if type_name == "cylinder" {
+                Box::new(Cylinder::new(base, end_point, radius, capped, material))
+            } else {
+                Box::new(Cone::new(base, end_point, radius, capped, material))
+            }
+        }
+        "torus" => Box::new(Torus::new(
+            table.require("center", to_vec3)?,
+            table.require("axis", to_direction)?,
+            table.require("major_radius", to_number)?,
+            table.require("minor_radius", to_number)?,
+            lookup_material(table, materials)?,
+        )),
+        "mesh" => Box::new(load_mesh(table, meshes, base_dir)?),
+        "medium" => {
+            let density_line = table.line_of("density");
+            let density = table.require("density", to_number)?;
+            if density <= 0. {
+                return line_error(density_line, "'density' must be positive".into());
+            }
+            let phase = lookup_material(table, materials)?;
+            let boundary_line = table.line_of("boundary");
+            // The boundary only delimits the volume, its material is never used
+            let boundary: BoxedHittable = match table.require("boundary", to_string)?.as_str() {
+                "sphere" => Box::new(Sphere::new(
+                    table.require("center", to_vec3)?,
+                    table.require("radius", to_number)?,
+                    Arc::clone(&phase),
+                )),
+                "box" => Box::new(BoxShape::new(
+                    table.require("min", to_vec3)?,
+                    table.require("max", to_vec3)?,
+                    Arc::clone(&phase),
+                )),
+                "mesh" => Box::new(load_mesh(table, meshes, base_dir)?),
+                other => {
+                    return line_error(
+                        boundary_line,
+                        format!("unknown medium boundary '{}'", other),
+                    )
+                }
+            };
+            Box::new(ConstantMedium::new(boundary, density, phase))
+        }
+        "volume" => {
+            let line = table.line_of("path");
+            let path = base_dir.join(table.require("path", to_string)?);
+            let grid = match VoxelGrid::load(&path) {
+                Ok(grid) => grid,
+                Err(err) => {
+                    return line_error(line, format!("could not load {}: {}", path.display(), err))
+                }
+            };
+            let bbox_line = table.line_of("max");
+            let bbox = AABB::new(
+                table.require("min", to_vec3)?,
+                table.require("max", to_vec3)?,
+            );
+            if (0..3).any(|a| bbox.max[a] <= bbox.min[a]) {
+                return line_error(bbox_line, "'max' must be above 'min' on every axis".into());
+            }
+            let density_line = table.line_of("density");
+            let density = table.get("density", to_number)?.unwrap_or(1.);
+            if density < 0. {
+                return line_error(density_line, "'density' must not be negative".into());
+            }
+            let phase = lookup_material(table, materials)?;
+            Box::new(GridMedium::new(grid, bbox, density, phase))
+        }
+        "sdf" => {
+            let sdf = table.require("sdf", |e| to_sdf(e, sdfs))?;
+            let bbox_line = table.line_of("max");
+            let bbox = AABB::new(
+                table.require("min", to_vec3)?,
+                table.require("max", to_vec3)?,
+            );
+            if (0..3).any(|a| bbox.max[a] <= bbox.min[a]) {
+                return line_error(bbox_line, "'max' must be above 'min' on every axis".into());
+            }
+            let epsilon_line = table.line_of("epsilon");
+            let epsilon = table.get("epsilon", to_number)?.unwrap_or(DEFAULT_EPSILON);
+            if epsilon <= 0. {
+                return line_error(epsilon_line, "'epsilon' must be positive".into());
+            }
+            let material = lookup_material(table, materials)?;
+            Box::new(SdfShape::new(sdf, bbox, material).with_epsilon(epsilon))
+        }
+        "csg" => {
+            let operation_line = table.line_of("operation");
+            let op = match table.require("operation", to_string)?.as_str() {
+                "union" => CsgOp::Union,
+                "intersection" => CsgOp::Intersection,
+                "difference" => CsgOp::Difference,
+                other => {
+                    return line_error(operation_line, format!("unknown CSG operation '{}'", other))
+                }
+            };
+            let left = objects.take(table, "left")?;
+            let right = objects.take(table, "right")?;
+            Box::new(Csg::new(op, left, right))
+        }
+        other => return line_error(type_line, format!("unknown object type '{}'", other)),
+    };
+    Ok(match build_transform(table)? {
+        Some(keyframes) if keyframes.len() == 1 => {
+            Box::new(Instance::new(Arc::from(object), keyframes[0].matrix()))
+        }
+        Some(keyframes) => Box::new(Instance::keyframed(Arc::from(object), keyframes)),
+        None => object,
+    })
+}
+
+fn load_mesh(
+    table: &mut Table,
+    meshes: &mut HashMap<PathBuf, Arc<TriangleMesh>>,
+    base_dir: &Path,
+) -> Result<Arc<TriangleMesh>, LineError> {
+    let line = table.line_of("path");
+    let path = base_dir.join(table.require("path", to_string)?);
+    if let Some(mesh) = meshes.get(&path) {
+        return Ok(Arc::clone(mesh));
+    }
+    match load_obj(&path) {
+        Ok(mesh) => Ok(Arc::clone(meshes.entry(path).or_insert(Arc::new(mesh)))),
+        Err(err) => line_error(line, format!("could not load mesh: {}", err)),
+    }
+}
+
+/// A scale factor, either uniform or per axis.
+fn value_to_scale(value: &Value) -> Option<Vec3> {
+    match value {
+        Value::Number(n) => Some(Vec3::new(*n, *n, *n)),
+        value => value_to_vec3(value),
+    }
+}
+
+/// Increasing keyframe times.
+fn to_times(entry: &Entry) -> Result<Vec<f64>, LineError> {
+    let times = match &entry.value {
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .map(|item| match item {
+                Value::Number(n) => Some(*n),
+                _ => None,
+            })
+            .collect::<Option<Vec<f64>>>(),
+        _ => None,
+    };
+    match times {
+        Some(times) if times.windows(2).all(|pair| pair[0] < pair[1]) => Ok(times),
+        _ => line_error(
+            entry.line,
+            format!("'{}' must be an array of increasing numbers", entry.key),
+        ),
+    }
+}
+
+/// One value for every keyframe, or a list with one value per keyframe.
+fn to_keyframe_values(
+    entry: &Entry,
+    count: usize,
+    convert: fn(&Value) -> Option<Vec3>,
+    expected: &str,
+) -> Result<Vec<Vec3>, LineError> {
+    if let Some(value) = convert(&entry.value) {
+        return Ok(vec![value; count]);
+    }
+    let values = match &entry.value {
+        Value::Array(items) if items.len() == count => {
+            items.iter().map(convert).collect::<Option<Vec<Vec3>>>()
+        }
+        _ => None,
+    };
+    values.map_or_else(
+        || {
+            line_error(
+                entry.line,
+                format!(
+                    "'{}' must be {}, or a list with one per time",
+                    entry.key, expected
+                ),
+            )
+        },
+        Ok,
+    )
+}
+
+/// Optional `scale`, `rotate` (degrees around x, then y, then z) and
+/// `translate` of an object, applied in that order. With `times`, each can be
+/// given once per time to animate the object.
+fn build_transform(table: &mut Table) -> Result<Option<Vec<Keyframe>>, LineError> {
+    let times = table.get("times", to_times)?;
+    let count = times.as_ref().map_or(1, Vec::len);
+    let scale_line = table.line_of("scale");
+    let scale = table.get("scale", |e| {
+        to_keyframe_values(
+            e,
+            count,
+            value_to_scale,
+            "a number or an array of 3 numbers",
+        )
+    })?;
+    let rotate = table.get("rotate", |e| {
+        to_keyframe_values(e, count, value_to_vec3, "an array of 3 numbers")
+    })?;
+    let translate = table.get("translate", |e| {
+        to_keyframe_values(e, count, value_to_vec3, "an array of 3 numbers")
+    })?;
+    if times.is_none() && scale.is_none() && rotate.is_none() && translate.is_none() {
+        return Ok(None);
+    }
+    let scale = scale.unwrap_or_else(|| vec![Vec3::new(1., 1., 1.); count]);
+    let rotate = rotate.unwrap_or_else(|| vec![Vec3::new(0., 0., 0.); count]);
+    let translate = translate.unwrap_or_else(|| vec![Vec3::new(0., 0., 0.); count]);
+    let times = times.unwrap_or_else(|| vec![0.]);
+    let keyframes: Vec<Keyframe> = (0..count)
+        .map(|i| Keyframe {
+            time: times[i],
+            scale: scale[i],
+            rotate: rotate[i],
+            translate: translate[i],
+        })
+        .collect();
+    // A scale of zero, or one small enough to lose the determinant, leaves
+    // the instance without an inverse to carry rays into object space
+    if keyframes.iter().any(|k| k.matrix().inverse().is_none()) {
+        return line_error(scale_line, "'scale' makes the transform singular".into());
+    }
+    Ok(Some(keyframes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    fn parse(text: &str) -> Result<SceneDescription, LineError> {
+        parse_scene(text, Path::new(""))
+    }
+
+    fn error_of(text: &str) -> (usize, String) {
+        let err = parse(text).err().expect("scene should not parse");
+        (err.line, err.message)
+    }
+
+    const SCENE: &str = r#"
+# A lit sphere on a ground plane
+[camera]
+lookfrom = [13, 2, 3]
+lookat = [0, 0, 0]
+vfov = 20
+aperture = 0.1
+
+[render]
+width = 400
+height = 225
+samples = 16
+
+[background]
+type = "constant"
+color = [0.1, 0.1, 0.1]
+
+[[object]]
+type = "plane"
+point = [0, 0, 0]
+normal = [0, 1, 0]
+material = "ground"
+
+[[object]]
+type = "triangle"
+vertices = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+material = "lamp"
+
+[[material]]
+name = "ground"    # defined after use
+type = "lambertian"
+color = [0.5, 0.5, 0.5]
+
+[[material]]
+name = "lamp"
+type = "diffuse_light"
+color = [1, 1, 1]
+intensity = 4
+"#;
+
+    #[test]
+    fn parses_scene() {
+        let scene = parse(SCENE).unwrap();
+        assert_eq!(scene.world.objects.len(), 2);
+        assert!(scene.world.objects[0].bounding_box(0., 0.).is_none());
+        assert!(scene.world.objects[1].is_emissive());
+        assert_eq!(scene.camera.lookfrom, Vec3::new(13., 2., 3.));
+        assert_eq!(scene.camera.vup, Vec3::new(0., 1., 0.));
+        assert_eq!(scene.camera.focus_dist, (13_f64 * 13. + 4. + 9.).sqrt());
+        assert_eq!(scene.settings.width, 400);
+        assert_eq!(
+            scene.settings.max_depth,
+            RenderSettings::default().max_depth
+        );
+        assert!(matches!(scene.background, Background::Constant(_)));
+    }
+
+    #[test]
+    fn parses_quads_and_boxes() {
+        let shapes = format!(
+            "{}{}",
+            SCENE,
+            r#"
+[[object]]
+type = "quad"
+corner = [0, 0, 0]
+u = [1, 0, 0]
+v = [0, 0, 1]
+material = "lamp"
+
+[[object]]
+type = "box"
+min = [0, 0, 0]
+max = [1, 2, 3]
+material = "ground"
+"#
+        );
+        let scene = parse(&shapes).unwrap();
+        assert_eq!(scene.world.objects.len(), 4);
+        assert!(scene.world.objects[2].is_emissive());
+        let bbox = scene.world.objects[3].bounding_box(0., 0.).unwrap();
+        assert_eq!(bbox.max, Vec3::new(1., 2., 3.));
+        assert_eq!(
+            error_of(&shapes.replace("v = [0, 0, 1]\n", "")),
+            (40, "[[object]] is missing 'v'".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_analytic_shapes() {
+        let shapes = format!(
+            "{}{}",
+            SCENE,
+            r#"
+[[object]]
+type = "cylinder"
+base = [0, 0, 0]
+top = [0, 2, 0]
+radius = 0.5
+capped = false
+material = "ground"
+
+[[object]]
+type = "cone"
+base = [0, 0, 0]
+apex = [0, 2, 0]
+radius = 0.5
+material = "ground"
+
+[[object]]
+type = "disk"
+center = [0, 3, 0]
+normal = [0, -1, 0]
+radius = 1
+material = "lamp"
+
+[[object]]
+type = "torus"
+center = [0, 0, 0]
+axis = [0, 1, 0]
+major_radius = 2
+minor_radius = 0.5
+material = "ground"
+"#
+        );
+        let scene = parse(&shapes).unwrap();
+        assert_eq!(scene.world.objects.len(), 6);
+        assert!(scene.world.objects[4].is_emissive());
+        assert_eq!(
+            error_of(&shapes.replace("capped = false", "capped = 0")),
+            (45, "'capped' must be true or false".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("apex = [0, 2, 0]", "apex = [0, 0, 0]")),
+            (51, "'apex' must differ from 'base'".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("normal = [0, -1, 0]", "normal = [0, 0, 0]")),
+            (58, "'normal' must not be zero".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_csg() {
+        let shapes = format!(
+            "{}{}",
+            SCENE,
+            r#"
+[[object]]
+name = "ball"
+type = "sphere"
+center = [0, 1, 0]
+radius = 1
+material = "ground"
+
+[[object]]
+name = "drill"
+type = "cylinder"
+base = [0, -1, 0]
+top = [0, 3, 0]
+radius = 0.3
+material = "ground"
+
+[[object]]
+type = "csg"
+operation = "difference"
+left = "ball"
+right = "drill"
+"#
+        );
+        // The operands are only rendered as part of the CSG object
+        let scene = parse(&shapes).unwrap();
+        assert_eq!(scene.world.objects.len(), 3);
+        let r = Ray::new(Vec3::new(0., 5., 0.), Vec3::new(0., -1., 0.));
+        assert!(scene.world.objects[2]
+            .hit(&r, 0.001, f64::INFINITY)
+            .is_none());
+
+        assert_eq!(
+            error_of(&shapes.replace("\"difference\"", "\"xor\"")),
+            (57, "unknown CSG operation 'xor'".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("right = \"drill\"", "right = \"ball\"")),
+            (59, "object 'ball' is already used".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("right = \"drill\"", "right = \"hole\"")),
+            (59, "unknown object 'hole'".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("name = \"drill\"", "name = \"ball\"")),
+            (48, "duplicate object 'ball'".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_sdfs() {
+        let shapes = format!(
+            "{}{}",
+            SCENE,
+            r#"
+[[sdf]]
+name = "ball"
+type = "sphere"
+radius = 1
+center = [0, 1, 0]
+
+[[sdf]]
+name = "ring"
+type = "torus"
+major_radius = 1
+minor_radius = 0.25
+
+[[sdf]]
+name = "blob"
+type = "smooth_union"
+left = "ball"
+right = "ring"
+smoothness = 0.2
+
+[[object]]
+type = "sdf"
+sdf = "blob"
+min = [-1.5, -0.5, -1.5]
+max = [1.5, 2.5, 1.5]
+material = "ground"
+"#
+        );
+        let scene = parse(&shapes).unwrap();
+        assert_eq!(scene.world.objects.len(), 3);
+        let r = Ray::new(Vec3::new(0., 5., 0.), Vec3::new(0., -1., 0.));
+        let rec = scene.world.objects[2]
+            .hit(&r, 0.001, f64::INFINITY)
+            .unwrap();
+        assert!((rec.t - 3.).abs() < 1e-3);
+
+        assert_eq!(
+            error_of(&shapes.replace("right = \"ring\"", "right = \"cube\"")),
+            (56, "unknown sdf 'cube'".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("max = [1.5, 2.5, 1.5]", "max = [1.5, -1, 1.5]")),
+            (63, "'max' must be above 'min' on every axis".to_string())
+        );
+        assert_eq!(
+            error_of(&shapes.replace("type = \"torus\"", "type = \"cone\"")),
+            (48, "unknown sdf type 'cone'".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_textures() {
+        let scene = parse(
+            r#"
+[camera]
+lookfrom = [0, 0, 1]
+lookat = [0, 0, 0]
+
+[[texture]]
+name = "dark"
+type = "solid"
+color = [0.1, 0.1, 0.1]
+
+[[texture]]
+name = "tiles"
+type = "checker"
+odd = "dark"
+even = [0.9, 0.9, 0.9]
+scale = 0.5
+
+[[texture]]
+name = "veins"
+type = "marble"
+scale = 4
+octaves = 5
+seed = 0
+
+[[material]]
+name = "floor"
+type = "lambertian"
+color = "tiles"
+
+[[material]]
+name = "stone"
+type = "metal"
+color = "veins"
+
+[[object]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "floor"
+"#,
+        );
+        assert_eq!(scene.unwrap().world.objects.len(), 1);
+
+        let base = "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\n";
+        assert_eq!(
+            error_of(&format!(
+                "{}[[material]]\nname = \"m\"\ntype = \"metal\"\ncolor = \"wood\"\n",
+                base
+            )),
+            (7, "unknown texture 'wood'".to_string())
+        );
+        assert_eq!(
+            error_of(&format!(
+                "{}[[texture]]\nname = \"t\"\ntype = \"image\"\npath = \"missing.png\"\n",
+                base
+            ))
+            .0,
+            7
+        );
+    }
+
+    #[test]
+    fn parses_physically_based_materials() {
+        let base = "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\n";
+        let materials = format!(
+            "{}{}",
+            base,
+            r#"
+[[material]]
+name = "gold"
+type = "conductor"
+metal = "gold"
+roughness = 0.3
+
+[[material]]
+name = "custom"
+type = "conductor"
+eta = [0.2, 0.9, 1.1]
+k = [3.9, 2.4, 2.1]
+
+[[material]]
+name = "frosted"
+type = "rough_dielectric"
+ior = 1.5
+roughness = 0.2
+
+[[material]]
+name = "paint"
+type = "principled"
+color = [0.1, 0.2, 0.6]
+metallic = 0.3
+roughness = 0.4
+clearcoat = 1
+
+[[object]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "frosted"
+
+[[object]]
+type = "sphere"
+center = [0, 2, 0]
+radius = 1
+material = "paint"
+"#
+        );
+        let scene = parse(&materials).unwrap();
+        assert_eq!(scene.world.objects.len(), 2);
+        assert_eq!(
+            error_of(&materials.replace("\"gold\"\nroughness", "\"tin\"\nroughness")),
+            (8, "unknown metal 'tin'".to_string())
+        );
+        assert_eq!(
+            error_of(&materials.replace("roughness = 0.3", "roughness = 2")),
+            (
+                9,
+                "'roughness' must be a number between 0 and 1".to_string()
+            )
+        );
+        assert_eq!(
+            error_of(&materials.replace("k = [3.9, 2.4, 2.1]\n", "")),
+            (11, "[[material]] is missing 'k'".to_string())
+        );
+        assert_eq!(
+            error_of(&materials.replace("clearcoat = 1", "clearcoat = -1")),
+            (
+                29,
+                "'clearcoat' must be a number between 0 and 1".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn parses_media() {
+        let base = r#"
+[camera]
+lookfrom = [0, 0, 1]
+lookat = [0, 0, 0]
+
+[[material]]
+name = "smoke"
+type = "isotropic"
+color = [0.2, 0.2, 0.2]
+
+[[object]]
+type = "medium"
+material = "smoke"
+"#;
+        let scene = parse(&format!(
+            "{}boundary = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\ndensity = 0.5\n",
+            base
+        ));
+        assert_eq!(scene.unwrap().world.objects.len(), 1);
+        let scene = parse(&format!(
+            "{}boundary = \"box\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]\ndensity = 0.5\n",
+            base
+        ));
+        assert_eq!(scene.unwrap().world.objects.len(), 1);
+        assert_eq!(
+            error_of(&format!("{}boundary = \"sphere\"\ndensity = 0\n", base)),
+            (15, "'density' must be positive".to_string())
+        );
+        assert_eq!(
+            error_of(&format!("{}boundary = \"cube\"\ndensity = 1\n", base)),
+            (14, "unknown medium boundary 'cube'".to_string())
+        );
+        let volume =
+            "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\n[[object]]\ntype = \"volume\"\n";
+        assert_eq!(
+            error_of(&format!("{}path = \"missing.vol\"\n", volume)).0,
+            6
+        );
+    }
+
+    #[test]
+    fn parses_transforms() {
+        let base = r#"
+[camera]
+lookfrom = [0, 0, 1]
+lookat = [0, 0, 0]
+
+[[material]]
+name = "white"
+type = "lambertian"
+color = [1, 1, 1]
+
+[[object]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "white"
+"#;
+        let scene = parse(&format!(
+            "{}scale = [2, 1, 1]\nrotate = [0, 0, 90]\ntranslate = [0, 0, -5]\n",
+            base
+        ))
+        .unwrap();
+        let bbox = scene.world.objects[0].bounding_box(0., 0.).unwrap();
+        assert!((bbox.min - Vec3::new(-1., -2., -6.)).length() < 1e-9);
+        assert!((bbox.max - Vec3::new(1., 2., -4.)).length() < 1e-9);
+        assert_eq!(
+            error_of(&format!("{}scale = [1, 0, 1]\n", base)),
+            (16, "'scale' makes the transform singular".to_string())
+        );
+        assert_eq!(
+            error_of(&format!("{}scale = 1e-14\n", base)),
+            (16, "'scale' makes the transform singular".to_string())
+        );
+
+        let scene = parse(&format!(
+            "{}times = [0, 1]\ntranslate = [[0, 0, 0], [4, 0, 0]]\nscale = 2\n",
+            base
+        ))
+        .unwrap();
+        let bbox = scene.world.objects[0].bounding_box(0., 1.).unwrap();
+        assert!((bbox.min - Vec3::new(-2., -2., -2.)).length() < 1e-9);
+        assert!((bbox.max - Vec3::new(6., 2., 2.)).length() < 1e-9);
+        assert_eq!(
+            error_of(&format!("{}times = [0, 1]\nrotate = [[0, 0, 0]]\n", base)),
+            (
+                17,
+                "'rotate' must be an array of 3 numbers, or a list with one per time".to_string()
+            )
+        );
+        assert_eq!(
+            error_of(&format!("{}times = [1, 0]\n", base)),
+            (
+                16,
+                "'times' must be an array of increasing numbers".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn parses_toml_syntax() {
+        let scene = parse(
+            r#"
+camera = { lookfrom = [13, 2, 3], lookat = [0, 0, 0] }
+
+material = [
+    { name = 'glass', type = "dielectric", ior = 1.5 },  # literal string
+]
+
+[[object]]
+type = "sphere"
+center = [0, 1, 0]
+radius = 1
+material = "glass"
+
+[[object]]
+type = "triangle"
+vertices = [
+    [0, 0, 0],
+    [1_000, 0, 0],  # trailing comma
+    [0, 0x10, 0],
+]
+material = "glass"
+"#,
+        )
+        .unwrap();
+        assert_eq!(scene.world.objects.len(), 2);
+        let bbox = scene.world.objects[1].bounding_box(0., 1.).unwrap();
+        assert!((bbox.max.x() - 1000.).abs() < 1e-3);
+        assert!((bbox.max.y() - 16.).abs() < 1e-3);
+        assert_eq!(scene.camera.lookfrom.x(), 13.);
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let base = "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\n";
+        assert_eq!(error_of(&format!("{}vfov = wide\n", base)).0, 4);
+        assert_eq!(
+            error_of(&format!(
+                "{}[[object]]\ntype = \"sphere\"\nradius = 1\n",
+                base
+            )),
+            (4, "[[object]] is missing 'center'".to_string())
+        );
+        assert_eq!(
+            error_of(&format!(
+                "{}[[object]]\ntype = \"sphere\"\ncenter = [0, 0]\n",
+                base
+            )),
+            (6, "'center' must be an array of 3 numbers".to_string())
+        );
+        assert_eq!(
+            error_of(&format!(
+                "{}[[object]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"gold\"\n",
+                base
+            )),
+            (8, "unknown material 'gold'".to_string())
+        );
+        assert_eq!(
+            error_of(&format!("{}fov = 30\n", base)),
+            (4, "unknown key 'fov' in [camera]".to_string())
+        );
+        assert_eq!(
+            error_of("[render]\nwidth = 10.5\n"),
+            (2, "'width' must be a positive integer".to_string())
+        );
+        assert_eq!(
+            error_of("[camera]\n[camera]\n"),
+            (2, "duplicate key".to_string())
+        );
+        assert_eq!(
+            error_of(&format!("{}up = {{ y = 1 }}\n", base)),
+            (4, "'up' must not be a table".to_string())
+        );
+        assert_eq!(
+            error_of("width = 3\n"),
+            (1, "key 'width' outside of any table".to_string())
+        );
+        assert_eq!(error_of(base), (3, "scene has no [[object]]".to_string()));
+    }
+}
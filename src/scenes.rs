@@ -0,0 +1,340 @@
+use crate::aabb::AABB;
+use crate::background::Background;
+use crate::camera::CameraSettings;
+use crate::hittable::{BoxedHittable, Hittable, HittableList, Sphere};
+use crate::instance::Instance;
+use crate::mat4::Mat4;
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Metal};
+use crate::medium::ConstantMedium;
+use crate::obj::{load_obj, ObjError};
+use crate::quad::{BoxShape, Quad};
+use crate::scene::{RenderSettings, SceneDescription};
+use crate::shapes::Plane;
+use crate::triangle::Triangle;
+use crate::utils::{random_double, random_double_range};
+use crate::vec3::{color, point3, unit_vector, Color, Point3, Vec3};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Names of the scenes that can be rendered without a scene file.
+pub const BUILTIN_SCENES: [&str; 4] = ["spheres", "triangles", "cornell", "cornell_smoke"];
+
+pub fn builtin_scene(name: &str) -> Option<SceneDescription> {
+    match name {
+        "spheres" => Some(random_spheres()),
+        "triangles" => Some(triangles()),
+        "cornell" => Some(cornell_box(setup_cornell_box)),
+        "cornell_smoke" => Some(cornell_box(setup_cornell_smoke)),
+        _ => None,
+    }
+}
+
+fn default_camera() -> CameraSettings {
+    CameraSettings {
+        lookfrom: point3(6., 2., 12.),
+        lookat: point3(0., 0., 0.),
+        vup: Vec3::new(0., 1., 0.),
+        vfov: 20.,
+        aperture: 0.1,
+        focus_dist: 10.,
+        time0: 0.,
+        time1: 0.,
+    }
+}
+
+fn random_spheres() -> SceneDescription {
+    let mut world = HittableList::new(vec![]);
+    setup_world(&mut world);
+    SceneDescription {
+        world,
+        camera: default_camera(),
+        background: Background::sky(),
+        settings: RenderSettings::default(),
+    }
+}
+
+fn triangles() -> SceneDescription {
+    let mut world = HittableList::new(vec![]);
+    setup_triangle_world(&mut world);
+    SceneDescription {
+        world,
+        camera: default_camera(),
+        background: Background::sky(),
+        settings: RenderSettings::default(),
+    }
+}
+
+fn cornell_box(setup: fn(&mut HittableList)) -> SceneDescription {
+    let mut world = HittableList::new(vec![]);
+    setup_cornell_walls(&mut world);
+    setup(&mut world);
+    SceneDescription {
+        world,
+        camera: CameraSettings {
+            lookfrom: point3(278., 278., -800.),
+            lookat: point3(278., 278., 0.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 40.,
+            aperture: 0.,
+            focus_dist: 10.,
+            time0: 0.,
+            time1: 0.,
+        },
+        background: Background::None,
+        settings: RenderSettings::default(),
+    }
+}
+
+/// An OBJ mesh standing on a ground plane, framed by the camera.
+pub fn mesh_viewer(path: &Path) -> Result<SceneDescription, ObjError> {
+    let mut world = HittableList::new(vec![]);
+    let bbox = setup_mesh_world(&mut world, path)?;
+    let mut camera = default_camera();
+    // Frame the mesh from the default viewing direction
+    let radius = (bbox.max - bbox.min).length() / 2.;
+    camera.lookat = (bbox.min + bbox.max) / 2.;
+    camera.focus_dist = 1.2 * radius / (camera.vfov / 2.).to_radians().sin();
+    camera.lookfrom = camera.lookat + unit_vector(&camera.lookfrom) * camera.focus_dist;
+    Ok(SceneDescription {
+        world,
+        camera,
+        // Evenly lit studio backdrop
+        background: Background::Constant(color(0.8, 0.8, 0.8)),
+        settings: RenderSettings::default(),
+    })
+}
+
+fn make_random_sphere(world: &mut HittableList, center: Point3, radius: f64) {
+    let choose_mat = random_double();
+    if (center - point3(4., radius, 0.)).length() > 0.9 {
+        if choose_mat < 0.6 {
+            let color = Color::random() * Color::random();
+            world.objects.push(Box::new(Sphere::new(
+                center,
+                radius,
+                Lambertian::new(color),
+            )));
+        } else if choose_mat < 0.95 {
+            let color = Color::random_range(0.5, 1.);
+            let fuzz = random_double_range(0., 0.5);
+            world.objects.push(Box::new(Sphere::new(
+                center,
+                radius,
+                Metal::new(color, fuzz),
+            )));
+        } else {
+            world
+                .objects
+                .push(Box::new(Sphere::new(center, radius, Dielectric::new(1.5))));
+        };
+    }
+}
+
+fn setup_world(world: &mut HittableList) {
+    let ground_material = Lambertian::new(color(0.5, 0.5, 0.8));
+    world.objects.push(Box::new(Plane::new(
+        point3(0., 0., 0.),
+        Vec3::new(0., 1., 0.),
+        ground_material,
+    )));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let posx = f64::from(a) + 0.9 * random_double();
+            let posy = 0.2;
+            let posz = f64::from(b) + 0.9 * random_double();
+            let center = point3(posx, posy, posz);
+            make_random_sphere(world, center, 0.2);
+        }
+    }
+    for a in -22..22 {
+        for b in -22..22 {
+            let posx = f64::from(a) / 2. + 0.9 * random_double();
+            let posy = 0.05;
+            let posz = f64::from(b) / 2. + 0.9 * random_double();
+            let center = point3(posx, posy, posz);
+            make_random_sphere(world, center, 0.05);
+        }
+    }
+    for a in -33..33 {
+        for b in -33..33 {
+            let posx = f64::from(a) / 3. + 0.9 * random_double();
+            let posy = 0.02;
+            let posz = f64::from(b) / 3. + 0.9 * random_double();
+            let center = point3(posx, posy, posz);
+            make_random_sphere(world, center, 0.02);
+        }
+    }
+
+    let mat_center = Dielectric::new(1.5);
+    world.objects.push(Box::new(Sphere::new(
+        Point3::new(0., 1., 0.),
+        1.0,
+        mat_center,
+    )));
+    let mat_left = Dielectric::new(1.5);
+    world.objects.push(Box::new(Sphere::new(
+        Point3::new(4., 1., 0.),
+        1.0,
+        mat_left,
+    )));
+    let mat_left2 = Dielectric::new(1.5);
+    world.objects.push(Box::new(Sphere::new(
+        Point3::new(4., 1., 0.),
+        -0.8,
+        mat_left2,
+    )));
+    let mat_right = Metal::new(color(0.7, 0.6, 0.5), 0.0);
+    world.objects.push(Box::new(Sphere::new(
+        Point3::new(-4., 1., 0.),
+        1.0,
+        mat_right,
+    )));
+    let mat_more = Dielectric::new(1.5);
+    let sphere_more = Box::new(Sphere::new(Point3::new(0., 1.5, -5.), 1.5, mat_more));
+    world.objects.push(sphere_more);
+}
+
+fn setup_triangle_world(world: &mut HittableList) {
+    let ground_material = Lambertian::new(color(0.5, 0.5, 0.8));
+    world.objects.push(Box::new(Plane::new(
+        point3(0., 0., 0.),
+        Vec3::new(0., 1., 0.),
+        ground_material,
+    )));
+
+    // Flat-shaded pyramid
+    let pyramid_material = Lambertian::new(color(0.8, 0.3, 0.2));
+    let apex = point3(-2.5, 1.5, 0.);
+    let base = [
+        point3(-3.25, 0., -0.75),
+        point3(-1.75, 0., -0.75),
+        point3(-1.75, 0., 0.75),
+        point3(-3.25, 0., 0.75),
+    ];
+    for i in 0..4 {
+        world.objects.push(Box::new(Triangle::new(
+            base[(i + 1) % 4],
+            base[i],
+            apex,
+            pyramid_material.clone(),
+        )));
+    }
+
+    // Smooth-shaded sphere made from a subdivided octahedron
+    let sphere_material = Metal::new(color(0.7, 0.6, 0.5), 0.1);
+    let center = point3(0.5, 1., 0.);
+    let axes = [
+        Vec3::new(1., 0., 0.),
+        Vec3::new(0., 0., -1.),
+        Vec3::new(-1., 0., 0.),
+        Vec3::new(0., 0., 1.),
+    ];
+    let mut faces = vec![];
+    for i in 0..4 {
+        let up = Vec3::new(0., 1., 0.);
+        faces.push([axes[i], axes[(i + 1) % 4], up]);
+        faces.push([axes[(i + 1) % 4], axes[i], -up]);
+    }
+    for _ in 0..3 {
+        faces = faces
+            .into_iter()
+            .flat_map(|[a, b, c]| {
+                let ab = unit_vector(&(a + b));
+                let bc = unit_vector(&(b + c));
+                let ca = unit_vector(&(c + a));
+                vec![[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            })
+            .collect();
+    }
+    for [a, b, c] in faces {
+        world.objects.push(Box::new(Triangle::with_normals(
+            center + a,
+            center + b,
+            center + c,
+            [a, b, c],
+            sphere_material.clone(),
+        )));
+    }
+}
+
+/// Load an OBJ mesh standing on a ground plane, returning the mesh bounding box.
+fn setup_mesh_world(world: &mut HittableList, path: &Path) -> Result<AABB, ObjError> {
+    let mesh = load_obj(path)?;
+    let bbox = mesh
+        .bounding_box(0., 0.)
+        .expect("Meshes always have a bounding box");
+    world.objects.push(Box::new(mesh));
+
+    let ground_material = Lambertian::new(color(0.5, 0.5, 0.8));
+    world.objects.push(Box::new(Plane::new(
+        bbox.min,
+        Vec3::new(0., 1., 0.),
+        ground_material,
+    )));
+    Ok(bbox)
+}
+
+fn setup_cornell_walls(world: &mut HittableList) {
+    let red = Lambertian::new(color(0.65, 0.05, 0.05));
+    let white = Lambertian::new(color(0.73, 0.73, 0.73));
+    let green = Lambertian::new(color(0.12, 0.45, 0.15));
+    let light = DiffuseLight::new(color(15., 15., 15.));
+
+    // Left, right, floor, ceiling and back walls
+    let walls: Vec<BoxedHittable> = vec![
+        Box::new(Quad::yz_rect(0., 555., 0., 555., 555., green)),
+        Box::new(Quad::yz_rect(0., 555., 0., 555., 0., red)),
+        Box::new(Quad::xz_rect(0., 555., 0., 555., 0., white.clone())),
+        Box::new(Quad::xz_rect(0., 555., 0., 555., 555., white.clone())),
+        Box::new(Quad::xy_rect(0., 555., 0., 555., 555., white)),
+        // Ceiling light, slightly below the ceiling so the two do not overlap
+        Box::new(Quad::xz_rect(213., 343., 227., 332., 554., light)),
+    ];
+    world.objects.extend(walls);
+}
+
+fn setup_cornell_box(world: &mut HittableList) {
+    let p = point3;
+    world.objects.push(Box::new(Sphere::new(
+        p(190., 90., 190.),
+        90.,
+        Dielectric::new(1.5),
+    )));
+    world.objects.push(Box::new(Sphere::new(
+        p(370., 120., 370.),
+        120.,
+        Metal::new(color(0.8, 0.85, 0.88), 0.05),
+    )));
+}
+
+/// Dark smoke and light fog filling the two boxes of the original Cornell box.
+fn setup_cornell_smoke(world: &mut HittableList) {
+    let smoke = Isotropic::new(color(0., 0., 0.));
+    let fog = Isotropic::new(color(1., 1., 1.));
+    let placed_box = |size: Vec3, angle: f64, offset: Vec3, phase: Isotropic| {
+        let shape = Arc::new(BoxShape::new(point3(0., 0., 0.), size, phase));
+        let transform = Mat4::translation(offset) * Mat4::rotation(Vec3::new(0., 1., 0.), angle);
+        Box::new(Instance::new(shape, transform))
+    };
+    world.objects.push(Box::new(ConstantMedium::new(
+        placed_box(
+            Vec3::new(165., 330., 165.),
+            15.,
+            Vec3::new(265., 0., 295.),
+            smoke.clone(),
+        ),
+        0.01,
+        smoke,
+    )));
+    world.objects.push(Box::new(ConstantMedium::new(
+        placed_box(
+            Vec3::new(165., 165., 165.),
+            -18.,
+            Vec3::new(130., 0., 65.),
+            fog.clone(),
+        ),
+        0.01,
+        fog,
+    )));
+}
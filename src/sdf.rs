@@ -0,0 +1,379 @@
+use crate::aabb::AABB;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{unit_vector, Point3, Vec3};
+use std::sync::Arc;
+
+/// Distance to the surface under which sphere tracing stops, unless set with
+/// `SdfShape::with_epsilon`.
+pub const DEFAULT_EPSILON: f64 = 1e-4;
+/// Sphere tracing steps after which a ray is taken to miss.
+const MAX_STEPS: usize = 512;
+
+/// Signed distance function, negative inside the shape.
+///
+/// Sphere tracing steps by the distance, so it must never overestimate the
+/// distance to the surface, but it may underestimate it (at the cost of more
+/// steps).
+pub trait Sdf {
+    fn distance(&self, p: Point3) -> f64;
+}
+
+pub type SharedSdf = Arc<dyn Sdf + Send + Sync>;
+
+impl<F> Sdf for F
+where
+    F: Fn(Point3) -> f64,
+{
+    fn distance(&self, p: Point3) -> f64 {
+        self(p)
+    }
+}
+
+fn map(v: Vec3, f: impl Fn(f64) -> f64) -> Vec3 {
+    Vec3 {
+        e: [f(v.x()), f(v.y()), f(v.z())],
+    }
+}
+
+/// Sphere of radius `radius` around the origin.
+pub struct SphereSdf {
+    radius: f64,
+}
+
+impl SphereSdf {
+    pub fn new(radius: f64) -> SphereSdf {
+        SphereSdf { radius }
+    }
+}
+
+impl Sdf for SphereSdf {
+    fn distance(&self, p: Point3) -> f64 {
+        p.length() - self.radius
+    }
+}
+
+/// Box centered on the origin, with its edges rounded off by `radius`.
+pub struct BoxSdf {
+    half_size: Vec3,
+    radius: f64,
+}
+
+impl BoxSdf {
+    /// Box from `-half_size` to `half_size`.
+    pub fn new(half_size: Vec3) -> BoxSdf {
+        BoxSdf::rounded(half_size, 0.)
+    }
+
+    /// Box of the same outer size as `BoxSdf::new`, with rounded edges.
+    pub fn rounded(half_size: Vec3, radius: f64) -> BoxSdf {
+        BoxSdf { half_size, radius }
+    }
+}
+
+impl Sdf for BoxSdf {
+    fn distance(&self, p: Point3) -> f64 {
+        let q =
+            map(p, f64::abs) - self.half_size + Vec3::new(self.radius, self.radius, self.radius);
+        let outside = map(q, |x| x.max(0.)).length();
+        let inside = q.x().max(q.y()).max(q.z()).min(0.);
+        outside + inside - self.radius
+    }
+}
+
+/// Torus around the y axis, with a tube of radius `minor_radius` following
+/// the circle of radius `major_radius`.
+pub struct TorusSdf {
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+impl TorusSdf {
+    pub fn new(major_radius: f64, minor_radius: f64) -> TorusSdf {
+        TorusSdf {
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl Sdf for TorusSdf {
+    fn distance(&self, p: Point3) -> f64 {
+        let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (ring * ring + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+}
+
+pub struct Translate {
+    sdf: SharedSdf,
+    offset: Vec3,
+}
+
+impl Translate {
+    pub fn new(sdf: SharedSdf, offset: Vec3) -> Translate {
+        Translate { sdf, offset }
+    }
+}
+
+impl Sdf for Translate {
+    fn distance(&self, p: Point3) -> f64 {
+        self.sdf.distance(p - self.offset)
+    }
+}
+
+/// Union of two shapes, blended where they are closer than `smoothness`.
+pub struct SmoothUnion {
+    a: SharedSdf,
+    b: SharedSdf,
+    smoothness: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(a: SharedSdf, b: SharedSdf, smoothness: f64) -> SmoothUnion {
+        SmoothUnion { a, b, smoothness }
+    }
+}
+
+impl Sdf for SmoothUnion {
+    /// Polynomial smooth minimum, which only differs from the minimum when
+    /// both distances are within `smoothness` of each other.
+    fn distance(&self, p: Point3) -> f64 {
+        let (a, b) = (self.a.distance(p), self.b.distance(p));
+        if self.smoothness <= 0. {
+            return a.min(b);
+        }
+        let h = (0.5 + 0.5 * (b - a) / self.smoothness).clamp(0., 1.);
+        b + (a - b) * h - self.smoothness * h * (1. - h)
+    }
+}
+
+/// Infinite copies of a shape, every `period` along each axis (not repeated
+/// along axes with a period of zero). The shape should fit within one period.
+pub struct Repeat {
+    sdf: SharedSdf,
+    period: Vec3,
+}
+
+impl Repeat {
+    pub fn new(sdf: SharedSdf, period: Vec3) -> Repeat {
+        Repeat { sdf, period }
+    }
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: Point3) -> f64 {
+        let mut q = p;
+        for a in 0..3 {
+            let period = self.period[a];
+            if period > 0. {
+                q.e[a] = (p[a] + 0.5 * period).rem_euclid(period) - 0.5 * period;
+            }
+        }
+        self.sdf.distance(q)
+    }
+}
+
+/// Shape twisted around the y axis, by `rate` radians per unit of height.
+pub struct Twist {
+    sdf: SharedSdf,
+    rate: f64,
+}
+
+impl Twist {
+    pub fn new(sdf: SharedSdf, rate: f64) -> Twist {
+        Twist { sdf, rate }
+    }
+}
+
+impl Sdf for Twist {
+    /// Twisting stretches space further from the axis, so the distance is
+    /// divided by the local stretch to avoid stepping past the surface.
+    fn distance(&self, p: Point3) -> f64 {
+        let (sin, cos) = (self.rate * p.y()).sin_cos();
+        let q = Vec3::new(cos * p.x() - sin * p.z(), p.y(), sin * p.x() + cos * p.z());
+        let rho = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        self.sdf.distance(q) / (1. + (self.rate * rho).powi(2)).sqrt()
+    }
+}
+
+/// Surface where a signed distance function is zero, found by sphere tracing:
+/// the ray advances by the distance to the surface until it is closer than
+/// `epsilon`. Normals are the gradient of the distance, by central
+/// differences. Rays are only traced inside `bbox`, which must contain the
+/// surface, and there are no texture coordinates.
+pub struct SdfShape<T>
+where
+    T: Material,
+{
+    sdf: SharedSdf,
+    bbox: AABB,
+    epsilon: f64,
+    material: T,
+}
+
+impl<T> SdfShape<T>
+where
+    T: Material,
+{
+    pub fn new(sdf: SharedSdf, bbox: AABB, material: T) -> SdfShape<T> {
+        SdfShape {
+            sdf,
+            bbox,
+            epsilon: DEFAULT_EPSILON,
+            material,
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> SdfShape<T> {
+        self.epsilon = epsilon;
+        self
+    }
+
+    fn normal(&self, p: Point3) -> Vec3 {
+        let h = self.epsilon;
+        let axes = [
+            Vec3::new(h, 0., 0.),
+            Vec3::new(0., h, 0.),
+            Vec3::new(0., 0., h),
+        ];
+        let gradient = axes.map(|d| self.sdf.distance(p + d) - self.sdf.distance(p - d));
+        unit_vector(&Vec3 { e: gradient })
+    }
+}
+
+impl<T> Hittable for SdfShape<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) =
+            self.bbox
+                .ray_interval(r.origin(), r.inv_direction(), t_min, t_max)?;
+        let ray_length = r.direction().length();
+        // Rays scattered off the surface start within epsilon of it, and have
+        // to get away before they can hit it again
+        let mut away = t_enter > t_min;
+        let mut t = t_enter;
+        for _ in 0..MAX_STEPS {
+            if t > t_exit {
+                return None;
+            }
+            let p = r.at(t);
+            // Tracing from inside works the same, towards the nearest surface
+            let distance = self.sdf.distance(p).abs();
+            if distance >= self.epsilon {
+                away = true;
+                t += distance / ray_length;
+            } else if away {
+                return Some(HitRecord::new(r, p, self.normal(p), t, &self.material));
+            } else {
+                t += self.epsilon / ray_length;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Sphere;
+    use crate::material::Lambertian;
+    use crate::vec3::{color, point3, random_unit_vector};
+
+    fn gray() -> Lambertian {
+        Lambertian::new(color(0.5, 0.5, 0.5))
+    }
+
+    fn cube(half: f64) -> AABB {
+        AABB::new(point3(-half, -half, -half), point3(half, half, half))
+    }
+
+    #[test]
+    fn distance_functions() {
+        let unit = Vec3::new(1., 1., 1.);
+        let cuboid = BoxSdf::new(unit);
+        assert_eq!(cuboid.distance(point3(3., 0., 0.)), 2.);
+        assert_eq!(cuboid.distance(point3(0., 0.5, 0.)), -0.5);
+        assert!((cuboid.distance(point3(2., 2., 1.)) - 2f64.sqrt()).abs() < 1e-12);
+        // Rounding keeps the faces in place but pulls in the corners
+        let rounded = BoxSdf::rounded(unit, 0.5);
+        assert_eq!(rounded.distance(point3(3., 0., 0.)), 2.);
+        let corner = 3f64.sqrt() * 0.5 - 0.5;
+        assert!((rounded.distance(point3(1., 1., 1.)) - corner).abs() < 1e-12);
+
+        let torus = TorusSdf::new(2., 0.5);
+        assert_eq!(torus.distance(point3(0., 0., 2.)), -0.5);
+        assert_eq!(torus.distance(point3(0., 0., 0.)), 1.5);
+
+        // Blends only near the junction, and never overestimates
+        let a: SharedSdf = Arc::new(Translate::new(Arc::new(SphereSdf::new(1.)), unit * -1.5));
+        let b: SharedSdf = Arc::new(Translate::new(Arc::new(SphereSdf::new(1.)), unit * 1.5));
+        let blob = SmoothUnion::new(Arc::clone(&a), Arc::clone(&b), 0.5);
+        let far = point3(-3., -3., -3.);
+        assert!((blob.distance(far) - a.distance(far)).abs() < 1e-12);
+        let middle = point3(0., 0., 0.);
+        assert!(blob.distance(middle) < a.distance(middle).min(b.distance(middle)));
+
+        let row = Repeat::new(Arc::new(SphereSdf::new(0.5)), Vec3::new(2., 0., 0.));
+        assert_eq!(row.distance(point3(4., 0., 0.)), -0.5);
+        assert_eq!(row.distance(point3(5., 0., 0.)), 0.5);
+        assert_eq!(row.distance(point3(0., 3., 0.)), 2.5);
+
+        // Quarter turn at y = 1
+        let twisted = Twist::new(
+            Arc::new(BoxSdf::new(Vec3::new(1., 2., 0.25))),
+            std::f64::consts::FRAC_PI_2,
+        );
+        assert!(twisted.distance(point3(0.5, 0., 0.)) < 0.);
+        assert!(twisted.distance(point3(0., 1., 0.5)) < 0.);
+        assert!(twisted.distance(point3(0.5, 1., 0.)) > 0.);
+    }
+
+    #[test]
+    fn sphere_tracing_matches_analytic_sphere() {
+        let sdf: SharedSdf = Arc::new(|p: Point3| p.length() - 1.);
+        let traced = SdfShape::new(sdf, cube(1.5), gray()).with_epsilon(1e-7);
+        let sphere = Sphere::new(point3(0., 0., 0.), 1., gray());
+        for _ in 0..100 {
+            let origin = random_unit_vector() * 4.;
+            let target = random_unit_vector() * 0.9;
+            let r = Ray::new(origin, target - origin);
+            let expected = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let rec = traced.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.t - expected.t).abs() < 1e-6);
+            assert!((rec.get_normal() - expected.get_normal()).length() < 1e-4);
+
+            // Leaving the surface, the ray does not hit it again right away,
+            // and from inside it finds the far side
+            let through = Ray::new(rec.p, r.direction());
+            let far = traced.hit(&through, 0.001, f64::INFINITY).unwrap();
+            let expected = sphere.hit(&through, 0.001, f64::INFINITY).unwrap();
+            assert!((far.t - expected.t).abs() < 1e-6);
+            assert!(!far.front_face);
+            let away = Ray::new(rec.p, rec.get_normal());
+            assert!(traced.hit(&away, 0.001, f64::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn tracing_stays_inside_bounds() {
+        let row: SharedSdf = Arc::new(Repeat::new(
+            Arc::new(SphereSdf::new(0.5)),
+            Vec3::new(2., 0., 0.),
+        ));
+        let shape = SdfShape::new(row, cube(3.), gray());
+        assert_eq!(shape.bounding_box(0., 0.).unwrap().max.x(), 3.);
+        let down = |x: f64| Ray::new(point3(x, 5., 0.), Vec3::new(0., -1., 0.));
+        let rec = shape.hit(&down(2.), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-3);
+        assert!(shape.hit(&down(1.), 0.001, f64::INFINITY).is_none());
+        // The copy at x = 4 is outside the bounds
+        assert!(shape.hit(&down(4.), 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -0,0 +1,762 @@
+use crate::aabb::{padded_box, surrounding_box, AABB};
+use crate::hittable::{HitRecord, Hittable, SurfaceSample};
+use crate::material::Material;
+use crate::onb::Onb;
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::{dot, unit_vector, Point3, Vec3};
+use std::f64::consts::PI;
+
+/// Position and orientation of a shape, whose axis is the local z axis.
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    origin: Point3,
+    onb: Onb,
+}
+
+impl Frame {
+    fn new(origin: Point3, axis: Vec3) -> Frame {
+        Frame {
+            origin,
+            onb: Onb::from_w(axis),
+        }
+    }
+
+    fn axis(&self) -> Vec3 {
+        self.onb.w
+    }
+
+    /// Components of `v` in the frame.
+    fn local_vector(&self, v: Vec3) -> Vec3 {
+        Vec3::new(dot(v, self.onb.u), dot(v, self.onb.v), dot(v, self.onb.w))
+    }
+
+    /// Origin and direction of `r` in the frame. The basis is orthonormal, so
+    /// the ray parameter is the same in both spaces.
+    fn local_ray(&self, r: &Ray) -> (Point3, Vec3) {
+        (
+            self.local_vector(r.origin() - self.origin),
+            self.local_vector(r.direction()),
+        )
+    }
+}
+
+/// Intersection found in the local frame of a shape.
+struct LocalHit {
+    t: f64,
+    /// Outward normal, in local coordinates
+    normal: Vec3,
+    u: f64,
+    v: f64,
+}
+
+impl LocalHit {
+    fn record<'a>(
+        self,
+        r: &Ray,
+        frame: &Frame,
+        material: &'a (dyn Material + Send + Sync),
+    ) -> HitRecord<'a> {
+        let n = self.normal;
+        let normal = unit_vector(&frame.onb.local(n.x(), n.y(), n.z()));
+        let mut rec = HitRecord::new(r, r.at(self.t), normal, self.t, material);
+        rec.u = self.u;
+        rec.v = self.v;
+        rec
+    }
+}
+
+fn nearest(a: Option<LocalHit>, b: Option<LocalHit>) -> Option<LocalHit> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.t < a.t { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Angle of `(x, y)` around the origin, as a fraction of a turn in `[0, 1)`.
+fn turn_fraction(x: f64, y: f64) -> f64 {
+    let turn = y.atan2(x) / (2. * PI);
+    if turn < 0. {
+        turn + 1.
+    } else {
+        turn
+    }
+}
+
+/// Box around the circle of radius `radius` centered on `center`, in the
+/// plane normal to the unit vector `axis`.
+fn circle_box(center: Point3, axis: Vec3, radius: f64) -> AABB {
+    let extent = [0, 1, 2].map(|a| radius * (1. - axis[a] * axis[a]).max(0.).sqrt());
+    let extent = Vec3 { e: extent };
+    AABB::new(center - extent, center + extent)
+}
+
+/// Disk of radius `radius` in the local plane `z = height`, with its outward
+/// normal along `facing` times the local z axis. `u` goes around the axis and
+/// `v` from the center to the rim.
+fn hit_cap(
+    o: Point3,
+    d: Vec3,
+    height: f64,
+    radius: f64,
+    facing: f64,
+    t_min: f64,
+    t_max: f64,
+) -> Option<LocalHit> {
+    if d.z() == 0. {
+        return None;
+    }
+    let t = (height - o.z()) / d.z();
+    if t < t_min || t > t_max {
+        return None;
+    }
+    let (x, y) = (o.x() + t * d.x(), o.y() + t * d.y());
+    let rho_squared = x * x + y * y;
+    if rho_squared > radius * radius {
+        return None;
+    }
+    Some(LocalHit {
+        t,
+        normal: Vec3::new(0., 0., facing),
+        u: turn_fraction(x, y),
+        v: rho_squared.sqrt() / radius,
+    })
+}
+
+/// Roots of `a t^2 + b t + c` in increasing order, computed without
+/// cancellation. Degrades to the linear equation when `a` is zero.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0. {
+        if b == 0. {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0. {
+        return Some((0., 0.));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some(if t0 < t1 { (t0, t1) } else { (t1, t0) })
+}
+
+/// One Newton step on `x`, kept only if it brings `f` closer to zero.
+fn newton_step(x: f64, f: impl Fn(f64) -> (f64, f64)) -> f64 {
+    let (value, slope) = f(x);
+    if slope == 0. {
+        return x;
+    }
+    let next = x - value / slope;
+    if f(next).0.abs() < value.abs() {
+        next
+    } else {
+        x
+    }
+}
+
+/// Largest real root of `x^3 + b x^2 + c x + d`.
+fn largest_cubic_root(b: f64, c: f64, d: f64) -> f64 {
+    // Depressed cubic y^3 + p y + q, with x = y - b / 3
+    let p = c - b * b / 3.;
+    let q = 2. * b * b * b / 27. - b * c / 3. + d;
+    let discriminant = q * q / 4. + p * p * p / 27.;
+    let y = if discriminant > 0. {
+        let sq = discriminant.sqrt();
+        (-q / 2. + sq).cbrt() + (-q / 2. - sq).cbrt()
+    } else if p < 0. {
+        // Three real roots, the largest has the smallest angle
+        let scale = (-p / 3.).sqrt();
+        let cos_3theta = (-q / (2. * scale * scale * scale)).clamp(-1., 1.);
+        2. * scale * (cos_3theta.acos() / 3.).cos()
+    } else {
+        0.
+    };
+    let cubic = |x: f64| (((x + b) * x + c) * x + d, (3. * x + 2. * b) * x + c);
+    (0..2).fold(y - b / 3., |x, _| newton_step(x, cubic))
+}
+
+/// Real roots of `x^4 + a x^3 + b x^2 + c x + d`, by Ferrari's method, then
+/// polished with Newton's method. Double roots may be repeated.
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Depressed quartic y^4 + p y^2 + q y + r, with x = y - a / 4
+    let a2 = a * a;
+    let p = b - 3. * a2 / 8.;
+    let q = c - a * b / 2. + a2 * a / 8.;
+    let r = d - a * c / 4. + a2 * b / 16. - 3. * a2 * a2 / 256.;
+    let mut roots = vec![];
+    let mut push_roots = |b: f64, c: f64| {
+        if let Some((y0, y1)) = solve_quadratic(1., b, c) {
+            roots.push(y0);
+            roots.push(y1);
+        }
+    };
+    // With m a root of the resolvent cubic, the quartic is the difference of
+    // the squares (y^2 + p / 2 + m)^2 and (s y - q / (2 s))^2, s^2 = 2 m
+    let m = largest_cubic_root(p, p * p / 4. - r, -q * q / 8.);
+    if m > 1e-12 * (1. + p.abs()) {
+        let s = (2. * m).sqrt();
+        push_roots(-s, p / 2. + m + q / (2. * s));
+        push_roots(s, p / 2. + m - q / (2. * s));
+    } else if let Some((z0, z1)) = solve_quadratic(1., p, r) {
+        // No odd term, so a quadratic in y^2
+        for &z in [z0, z1].iter().filter(|&&z| z >= 0.) {
+            push_roots(0., -z);
+        }
+    }
+    let quartic = |x: f64| {
+        (
+            (((x + a) * x + b) * x + c) * x + d,
+            ((4. * x + 3. * a) * x + 2. * b) * x + c,
+        )
+    };
+    roots
+        .into_iter()
+        .map(|y| (0..2).fold(y - a / 4., |x, _| newton_step(x, quartic)))
+        .collect()
+}
+
+/// Flat disk of radius `radius` around `center`, facing along `normal`.
+/// Texture coordinates go around the normal (`u`) and from the center to the
+/// rim (`v`).
+pub struct Disk<T>
+where
+    T: Material,
+{
+    frame: Frame,
+    radius: f64,
+    material: T,
+}
+
+impl<T> Disk<T>
+where
+    T: Material,
+{
+    pub fn new(center: Point3, normal: Vec3, radius: f64, material: T) -> Disk<T> {
+        Disk {
+            frame: Frame::new(center, normal),
+            radius,
+            material,
+        }
+    }
+
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<LocalHit> {
+        let (o, d) = self.frame.local_ray(r);
+        hit_cap(o, d, 0., self.radius, 1., t_min, t_max)
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+}
+
+impl<T> Hittable for Disk<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.intersect(r, t_min, t_max)
+            .map(|hit| hit.record(r, &self.frame, &self.material))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let bbox = circle_box(self.frame.origin, self.frame.axis(), self.radius);
+        Some(padded_box(bbox.min, bbox.max))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Uniformly sample the area of the disk.
+    fn sample(&self, origin: Point3, _time: f64) -> Option<SurfaceSample> {
+        let rho = self.radius * random_double().sqrt();
+        let phi = 2. * PI * random_double();
+        let p = self.frame.origin + self.frame.onb.local(rho * phi.cos(), rho * phi.sin(), 0.);
+        let to_p = p - origin;
+        let cosine = dot(unit_vector(&to_p), self.frame.axis()).abs();
+        if cosine < 1e-8 {
+            return None;
+        }
+        Some(SurfaceSample {
+            p,
+            pdf: to_p.length_squared() / (cosine * self.area()),
+        })
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
+        match self.intersect(&Ray::new(origin, direction), 0.001, f64::INFINITY) {
+            Some(hit) => {
+                let cosine = dot(unit_vector(&direction), self.frame.axis()).abs();
+                (hit.t * hit.t * direction.length_squared()) / (cosine * self.area())
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// Infinite plane through `point`, facing along `normal`. It has no bounding
+/// box, so a BVH keeps it out of the tree. Texture coordinates repeat every
+/// unit along two directions of the plane, tiling image textures over it.
+pub struct Plane<T>
+where
+    T: Material,
+{
+    frame: Frame,
+    material: T,
+}
+
+impl<T> Plane<T>
+where
+    T: Material,
+{
+    pub fn new(point: Point3, normal: Vec3, material: T) -> Plane<T> {
+        Plane {
+            frame: Frame::new(point, normal),
+            material,
+        }
+    }
+}
+
+impl<T> Hittable for Plane<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(r);
+        if d.z() == 0. {
+            return None;
+        }
+        let t = -o.z() / d.z();
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let hit = LocalHit {
+            t,
+            normal: Vec3::new(0., 0., 1.),
+            u: (o.x() + t * d.x()).rem_euclid(1.),
+            v: (o.y() + t * d.y()).rem_euclid(1.),
+        };
+        Some(hit.record(r, &self.frame, &self.material))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        None
+    }
+}
+
+/// Cylinder of radius `radius` around the segment from `base` to `top`,
+/// closed by disks at both ends when `capped`. On the side, `u` goes around
+/// the axis and `v` from the base to the top; caps are mapped like disks.
+pub struct Cylinder<T>
+where
+    T: Material,
+{
+    frame: Frame,
+    height: f64,
+    radius: f64,
+    capped: bool,
+    material: T,
+}
+
+impl<T> Cylinder<T>
+where
+    T: Material,
+{
+    pub fn new(base: Point3, top: Point3, radius: f64, capped: bool, material: T) -> Cylinder<T> {
+        Cylinder {
+            frame: Frame::new(base, top - base),
+            height: (top - base).length(),
+            radius,
+            capped,
+            material,
+        }
+    }
+}
+
+impl<T> Hittable for Cylinder<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(r);
+        let (radius, height) = (self.radius, self.height);
+        let mut closest = None;
+        let roots = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y(),
+            2. * (o.x() * d.x() + o.y() * d.y()),
+            o.x() * o.x() + o.y() * o.y() - radius * radius,
+        );
+        if let Some((t0, t1)) = roots {
+            for &t in [t0, t1].iter() {
+                let p = o + d * t;
+                if t >= t_min && t <= t_max && (0. ..=height).contains(&p.z()) {
+                    closest = Some(LocalHit {
+                        t,
+                        normal: Vec3::new(p.x() / radius, p.y() / radius, 0.),
+                        u: turn_fraction(p.x(), p.y()),
+                        v: p.z() / height,
+                    });
+                    break;
+                }
+            }
+        }
+        if self.capped {
+            let t_max = closest.as_ref().map_or(t_max, |hit| hit.t);
+            let bottom = hit_cap(o, d, 0., radius, -1., t_min, t_max);
+            let top = hit_cap(o, d, height, radius, 1., t_min, t_max);
+            closest = nearest(closest, nearest(bottom, top));
+        }
+        closest.map(|hit| hit.record(r, &self.frame, &self.material))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let axis = self.frame.axis();
+        let top = self.frame.origin + axis * self.height;
+        Some(surrounding_box(
+            &circle_box(self.frame.origin, axis, self.radius),
+            &circle_box(top, axis, self.radius),
+        ))
+    }
+}
+
+/// Cone with a base of radius `radius` around `base`, narrowing to a point at
+/// `apex`, closed by the base disk when `capped`. On the side, `u` goes around
+/// the axis and `v` from the base to the apex; the cap is mapped like a disk.
+pub struct Cone<T>
+where
+    T: Material,
+{
+    frame: Frame,
+    height: f64,
+    radius: f64,
+    capped: bool,
+    material: T,
+}
+
+impl<T> Cone<T>
+where
+    T: Material,
+{
+    pub fn new(base: Point3, apex: Point3, radius: f64, capped: bool, material: T) -> Cone<T> {
+        Cone {
+            frame: Frame::new(base, apex - base),
+            height: (apex - base).length(),
+            radius,
+            capped,
+            material,
+        }
+    }
+}
+
+impl<T> Hittable for Cone<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(r);
+        // The radius at height z is k (h - z)
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let above = self.height - o.z();
+        let roots = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z(),
+            2. * (o.x() * d.x() + o.y() * d.y() + k2 * above * d.z()),
+            o.x() * o.x() + o.y() * o.y() - k2 * above * above,
+        );
+        let mut closest = None;
+        if let Some((t0, t1)) = roots {
+            for &t in [t0, t1].iter() {
+                let p = o + d * t;
+                // The equation also holds on the mirrored cone above the apex
+                if t >= t_min && t <= t_max && (0. ..=self.height).contains(&p.z()) {
+                    let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+                    let normal = if rho > 0. {
+                        Vec3::new(p.x() / rho, p.y() / rho, k)
+                    } else {
+                        Vec3::new(0., 0., 1.)
+                    };
+                    closest = Some(LocalHit {
+                        t,
+                        normal,
+                        u: turn_fraction(p.x(), p.y()),
+                        v: p.z() / self.height,
+                    });
+                    break;
+                }
+            }
+        }
+        if self.capped {
+            let t_max = closest.as_ref().map_or(t_max, |hit| hit.t);
+            closest = nearest(closest, hit_cap(o, d, 0., self.radius, -1., t_min, t_max));
+        }
+        closest.map(|hit| hit.record(r, &self.frame, &self.material))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let axis = self.frame.axis();
+        let apex = self.frame.origin + axis * self.height;
+        Some(surrounding_box(
+            &circle_box(self.frame.origin, axis, self.radius),
+            &AABB::new(apex, apex),
+        ))
+    }
+}
+
+/// Torus around `center`: a tube of radius `minor_radius` following the
+/// circle of radius `major_radius` in the plane normal to `axis`. `u` goes
+/// around the axis and `v` around the tube, starting from the outer equator.
+pub struct Torus<T>
+where
+    T: Material,
+{
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    material: T,
+}
+
+impl<T> Torus<T>
+where
+    T: Material,
+{
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: T,
+    ) -> Torus<T> {
+        Torus {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+
+impl<T> Hittable for Torus<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.local_ray(r);
+        // Work in distances along the ray, from a point on the bounding
+        // sphere, so that the quartic stays well conditioned far away
+        let length = d.length();
+        let d = d / length;
+        let (big, small) = (self.major_radius, self.minor_radius);
+        let bound = big + small;
+        let (enter, exit) =
+            solve_quadratic(1., 2. * dot(o, d), o.length_squared() - bound * bound)?;
+        if exit < t_min * length || enter > t_max * length {
+            return None;
+        }
+        let o = o + d * enter;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along p = o + s d
+        let (b, c) = (
+            2. * dot(o, d),
+            o.length_squared() + big * big - small * small,
+        );
+        let four_r2 = 4. * big * big;
+        let roots = solve_quartic(
+            2. * b,
+            b * b + 2. * c - four_r2 * (d.x() * d.x() + d.y() * d.y()),
+            2. * b * c - 2. * four_r2 * (o.x() * d.x() + o.y() * d.y()),
+            c * c - four_r2 * (o.x() * o.x() + o.y() * o.y()),
+        );
+        let s = roots
+            .into_iter()
+            .filter(|s| {
+                let t = (enter + s) / length;
+                t >= t_min && t <= t_max
+            })
+            .min_by(f64::total_cmp)?;
+
+        let p = o + d * s;
+        let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        let normal = if rho > 0. {
+            let ring = Vec3::new(p.x(), p.y(), 0.) * (big / rho);
+            (p - ring) / small
+        } else {
+            Vec3::new(0., 0., p.z().signum())
+        };
+        let hit = LocalHit {
+            t: (enter + s) / length,
+            normal,
+            u: turn_fraction(p.x(), p.y()),
+            v: turn_fraction(rho - big, p.z()),
+        };
+        Some(hit.record(r, &self.frame, &self.material))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        let ring = circle_box(self.frame.origin, self.frame.axis(), self.major_radius);
+        let tube = Vec3::new(self.minor_radius, self.minor_radius, self.minor_radius);
+        Some(AABB::new(ring.min - tube, ring.max + tube))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::{color, point3, random_unit_vector};
+
+    fn gray() -> Lambertian {
+        Lambertian::new(color(0.5, 0.5, 0.5))
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn solves_quartics() {
+        // (x - 1)(x - 2)(x + 3)(x - 0.5)
+        let mut roots = solve_quartic(-0.5, -7., 9.5, -3.);
+        roots.sort_by(f64::total_cmp);
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip([-3., 0.5, 1., 2.].iter()) {
+            assert!((root - expected).abs() < 1e-9);
+        }
+        // (x^2 + 1)(x^2 - 4), with no odd terms
+        let mut roots = solve_quartic(0., -3., 0., -4.);
+        roots.sort_by(f64::total_cmp);
+        assert_eq!(roots.len(), 2);
+        assert!((roots[0] + 2.).abs() < 1e-12 && (roots[1] - 2.).abs() < 1e-12);
+        assert!(solve_quartic(0., 2., 0., 1.).is_empty());
+    }
+
+    #[test]
+    fn cylinders_and_cones_hit_sides_and_caps() {
+        // Lying along x, from 0 to 4
+        let base = point3(0., 0., 0.);
+        let top = point3(4., 0., 0.);
+        let capped = Cylinder::new(base, top, 1., true, gray());
+        let open = Cylinder::new(base, top, 1., false, gray());
+
+        let down = Ray::new(point3(1., 5., 0.), Vec3::new(0., -1., 0.));
+        let rec = capped.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-9);
+        assert!(close(rec.get_normal(), Vec3::new(0., 1., 0.)));
+        assert!((rec.v - 0.25).abs() < 1e-9);
+
+        let along = Ray::new(point3(-5., 0.5, 0.), Vec3::new(1., 0., 0.));
+        let rec = capped.hit(&along, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 5.).abs() < 1e-9);
+        assert!(close(rec.get_normal(), Vec3::new(-1., 0., 0.)));
+        assert!((rec.v - 0.5).abs() < 1e-9);
+        // Without caps, the ray goes through the tube
+        assert!(open.hit(&along, 0.001, f64::INFINITY).is_none());
+        // and sees the inside of the far wall through the open end
+        let slanted = Ray::new(point3(-1., 0., 0.), Vec3::new(1., 0.5, 0.));
+        assert!(!open.hit(&slanted, 0.001, f64::INFINITY).unwrap().front_face);
+
+        let bbox = capped.bounding_box(0., 0.).unwrap();
+        assert!(close(bbox.min, point3(0., -1., -1.)));
+        assert!(close(bbox.max, point3(4., 1., 1.)));
+
+        // Apex up at y = 2, so the side is at 45 degrees
+        let cone = Cone::new(base, point3(0., 2., 0.), 2., true, gray());
+        let r = Ray::new(point3(5., 1., 0.), Vec3::new(-1., 0., 0.));
+        let rec = cone.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-9);
+        let expected = unit_vector(&Vec3::new(1., 1., 0.));
+        assert!(close(rec.get_normal(), expected));
+        // Not on the mirrored cone above the apex
+        let r = Ray::new(point3(5., 3., 0.), Vec3::new(-1., 0., 0.));
+        assert!(cone.hit(&r, 0.001, f64::INFINITY).is_none());
+        let up = Ray::new(point3(0.5, -1., 0.), Vec3::new(0., 1., 0.));
+        let rec = cone.hit(&up, 0.001, f64::INFINITY).unwrap();
+        assert!(close(rec.get_normal(), Vec3::new(0., -1., 0.)));
+        assert!((rec.v - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn torus_hits_lie_on_surface() {
+        let center = point3(1., 2., 3.);
+        let axis = unit_vector(&Vec3::new(1., 1., 0.));
+        let torus = Torus::new(center, axis, 2., 0.5, gray());
+
+        // Straight through the hole
+        let r = Ray::new(center - axis * 10., axis);
+        assert!(torus.hit(&r, 0.001, f64::INFINITY).is_none());
+        // Across both sides of the tube, from far away
+        let side = Frame::new(center, axis).onb.u;
+        let r = Ray::new(center - side * 1000., side * 0.5);
+        let rec = torus.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - (1000. - 2.5) * 2.).abs() < 1e-6);
+        assert!(close(rec.get_normal(), -side));
+        let rec = torus.hit(&r, rec.t + 0.01, f64::INFINITY).unwrap();
+        assert!((rec.t - (1000. - 1.5) * 2.).abs() < 1e-6);
+
+        let bbox = torus.bounding_box(0., 0.).unwrap();
+        let ring = 2. / 2f64.sqrt() + 0.5;
+        assert!(close(bbox.max - center, Vec3::new(ring, ring, 2.5)));
+
+        // Random rays towards the torus hit points of the surface, where the
+        // normal is the direction from the center line of the tube
+        let frame = Frame::new(center, axis);
+        for _ in 0..1000 {
+            let origin = center + random_unit_vector() * 5.;
+            let target = center + random_unit_vector() * 2.;
+            let r = Ray::new(origin, target - origin);
+            if let Some(rec) = torus.hit(&r, 0.001, f64::INFINITY) {
+                let local = frame.local_vector(rec.p - center);
+                let rho = (local.x() * local.x() + local.y() * local.y()).sqrt();
+                let tube = ((rho - 2.).powi(2) + local.z() * local.z()).sqrt();
+                assert!((tube - 0.5).abs() < 1e-6);
+                let ring = center + frame.onb.local(local.x(), local.y(), 0.) * (2. / rho);
+                let outward = if rec.front_face {
+                    rec.get_normal()
+                } else {
+                    -rec.get_normal()
+                };
+                assert!((outward - unit_vector(&(rec.p - ring))).length() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn disk_sample_pdf_matches_pdf_value() {
+        let disk = Disk::new(point3(0., 3., 0.), Vec3::new(0., -1., 1.), 1., gray());
+        let origin = point3(0.2, 0., 0.1);
+        for _ in 0..100 {
+            let sample = disk.sample(origin, 0.).unwrap();
+            let pdf = disk.pdf_value(origin, sample.p - origin, 0.);
+            assert!((pdf - sample.pdf).abs() < 1e-9 * pdf);
+        }
+        let bbox = disk.bounding_box(0., 0.).unwrap();
+        let extent = 1. / 2f64.sqrt();
+        assert!(close(bbox.max, point3(1., 3. + extent, extent)));
+    }
+
+    #[test]
+    fn plane_hits_from_both_sides() {
+        let plane = Plane::new(point3(0., -1., 0.), Vec3::new(0., 1., 0.), gray());
+        assert!(plane.bounding_box(0., 1.).is_none());
+
+        let r = Ray::new(point3(10.25, 4., -3.5), Vec3::new(0., -1., 0.));
+        let rec = plane.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 5.).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!(close(rec.get_normal(), Vec3::new(0., 1., 0.)));
+        assert!((0. ..1.).contains(&rec.u) && (0. ..1.).contains(&rec.v));
+
+        // Far away, at a grazing angle
+        let r = Ray::new(point3(0., 0., 0.), Vec3::new(1e4, -1., 0.));
+        assert!((plane.hit(&r, 0.001, f64::INFINITY).unwrap().t - 1.).abs() < 1e-9);
+        let r = Ray::new(point3(0., -3., 0.), Vec3::new(0.3, 1., 0.2));
+        assert!(!plane.hit(&r, 0.001, f64::INFINITY).unwrap().front_face);
+        let r = Ray::new(point3(0., 0., 0.), Vec3::new(1., 0., 0.));
+        assert!(plane.hit(&r, 0.001, f64::INFINITY).is_none());
+        let r = Ray::new(point3(0., 0., 0.), Vec3::new(0., 1., 0.));
+        assert!(plane.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::image::srgb_to_linear;
+use crate::perlin::Perlin;
+use crate::vec3::{color, Color, Point3};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Color varying over a surface, looked up by texture coordinates or position.
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+pub type SharedTexture = Arc<dyn Texture + Send + Sync>;
+
+#[derive(Copy, Clone)]
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+/// 3D checker pattern of cubes with side `scale`, alternating between two textures.
+pub struct CheckerTexture {
+    odd: SharedTexture,
+    even: SharedTexture,
+    scale: f64,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: SharedTexture, even: SharedTexture, scale: f64) -> CheckerTexture {
+        CheckerTexture { odd, even, scale }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let cell = |x: f64| (x / self.scale).floor() as i64;
+        if (cell(p.x()) + cell(p.y()) + cell(p.z())) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoisePattern {
+    /// Smooth Perlin noise
+    Noise,
+    /// Sum of noise octaves, with a cloudy look
+    Turbulence,
+    /// Veins along z, distorted by turbulence
+    Marble,
+    /// Rings around the y axis, distorted by turbulence
+    Wood,
+}
+
+/// Procedural texture blending two colors according to a noise pattern.
+pub struct NoiseTexture {
+    perlin: Perlin,
+    pattern: NoisePattern,
+    /// Frequency of the pattern, in features per world unit
+    scale: f64,
+    octaves: u32,
+    low: Color,
+    high: Color,
+}
+
+impl NoiseTexture {
+    pub fn new(pattern: NoisePattern, scale: f64, octaves: u32, seed: u64) -> NoiseTexture {
+        let (low, high) = match pattern {
+            NoisePattern::Wood => (color(0.35, 0.18, 0.07), color(0.75, 0.5, 0.27)),
+            NoisePattern::Marble => (color(0.15, 0.15, 0.17), color(0.95, 0.95, 0.93)),
+            _ => (color(0., 0., 0.), color(1., 1., 1.)),
+        };
+        NoiseTexture {
+            perlin: Perlin::new(seed),
+            pattern,
+            scale,
+            octaves,
+            low,
+            high,
+        }
+    }
+
+    pub fn with_colors(mut self, low: Color, high: Color) -> NoiseTexture {
+        self.low = low;
+        self.high = high;
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let q = p * self.scale;
+        let t = match self.pattern {
+            NoisePattern::Noise => 0.5 * (1. + self.perlin.noise(q)),
+            NoisePattern::Turbulence => self.perlin.turbulence(q, self.octaves),
+            NoisePattern::Marble => {
+                0.5 * (1. + (q.z() + 10. * self.perlin.turbulence(q, self.octaves)).sin())
+            }
+            NoisePattern::Wood => {
+                let radius = (q.x() * q.x() + q.z() * q.z()).sqrt();
+                (radius + 0.5 * self.perlin.turbulence(q, self.octaves)).fract()
+            }
+        };
+        let t = t.clamp(0., 1.);
+        self.low * (1. - t) + self.high * t
+    }
+}
+
+/// Texture read from an 8-bit PNG file, with `(0, 0)` at the bottom left.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    /// Linear colors, row by row from the top
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn load(path: &Path) -> io::Result<ImageTexture> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let channels = info.color_type.samples();
+        let to_linear = |byte: u8| srgb_to_linear(f64::from(byte) / 255.);
+        let pixels = buf[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|px| match channels {
+                // Grayscale, with or without alpha
+                1 | 2 => {
+                    let l = to_linear(px[0]);
+                    color(l, l, l)
+                }
+                _ => color(to_linear(px[0]), to_linear(px[1]), to_linear(px[2])),
+            })
+            .collect();
+        Ok(ImageTexture {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        let u = u.clamp(0., 1.);
+        let v = 1. - v.clamp(0., 1.);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::point3;
+
+    #[test]
+    fn checker_alternates() {
+        let checker = CheckerTexture::new(
+            Arc::new(SolidColor::new(color(1., 1., 1.))),
+            Arc::new(SolidColor::new(color(0., 0., 0.))),
+            0.5,
+        );
+        let at = |x, y, z| checker.value(0., 0., point3(x, y, z));
+        assert_eq!(at(0.1, 0.1, 0.1), color(0., 0., 0.));
+        assert_eq!(at(0.6, 0.1, 0.1), color(1., 1., 1.));
+        assert_eq!(at(-0.1, 0.1, 0.1), color(1., 1., 1.));
+        assert_eq!(at(0.6, 0.6, 0.1), color(0., 0., 0.));
+    }
+
+    #[test]
+    fn noise_stays_between_colors() {
+        let low = color(0.2, 0.1, 0.);
+        let high = color(0.6, 0.5, 0.4);
+        for pattern in [
+            NoisePattern::Noise,
+            NoisePattern::Turbulence,
+            NoisePattern::Marble,
+            NoisePattern::Wood,
+        ] {
+            let texture = NoiseTexture::new(pattern, 4., 7, 1).with_colors(low, high);
+            for i in 0..100 {
+                let c = texture.value(0., 0., point3(0.37 * f64::from(i), 0.1, -0.2));
+                for a in 0..3 {
+                    assert!(c.e[a] >= low.e[a] - 1e-12 && c.e[a] <= high.e[a] + 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn image_lookup_starts_at_bottom_left() {
+        let texture = ImageTexture {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                color(1., 0., 0.),
+                color(0., 1., 0.),
+                color(0., 0., 1.),
+                color(1., 1., 1.),
+            ],
+        };
+        let p = point3(0., 0., 0.);
+        assert_eq!(texture.value(0., 0., p), color(0., 0., 1.));
+        assert_eq!(texture.value(1., 1., p), color(0., 1., 0.));
+        assert_eq!(texture.value(0.2, 0.9, p), color(1., 0., 0.));
+    }
+}
@@ -0,0 +1,356 @@
+use crate::aabb::AABB;
+use crate::bvh::{BVHStats, LinearBVH};
+use crate::hittable::SurfaceSample;
+use crate::hittable::{BoxedHittable, HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::utils::random_double;
+use crate::vec3::{cross, dot, unit_vector, Point3, Vec3};
+use std::sync::Arc;
+
+// Flat triangles lying in an axis plane would get a zero-thickness box, which
+// `AABB::hit` always rejects.
+const BOX_PADDING: f64 = 1e-4;
+
+pub struct Triangle<T>
+where
+    T: Material,
+{
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    material: T,
+}
+
+impl<T> Triangle<T>
+where
+    T: Material,
+{
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: T) -> Triangle<T> {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: None,
+            material,
+        }
+    }
+
+    /// Triangle with per-vertex normals, interpolated across the face for smooth shading.
+    pub fn with_normals(
+        v0: Point3,
+        v1: Point3,
+        v2: Point3,
+        normals: [Vec3; 3],
+        material: T,
+    ) -> Triangle<T> {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: Some(normals),
+            material,
+        }
+    }
+}
+
+/// Watertight ray/triangle intersection (Woop, Benthin and Wald, 2013).
+///
+/// Returns the ray parameter and the barycentric coordinates `(u, v)` of the
+/// hit point with respect to the second and third vertices.
+pub fn intersect_triangle(
+    r: &Ray,
+    vertices: &[Point3; 3],
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let dir = r.direction();
+    // Permute axes so that the dominant direction component becomes z
+    let kz = max_dimension(dir);
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0. {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    let sx = dir[kx] / dir[kz];
+    let sy = dir[ky] / dir[kz];
+    let sz = 1. / dir[kz];
+
+    let a = vertices[0] - r.origin();
+    let b = vertices[1] - r.origin();
+    let c = vertices[2] - r.origin();
+
+    // Shear the vertices so the ray points along +z from the origin
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    let e0 = cx * by - cy * bx;
+    let e1 = ax * cy - ay * cx;
+    let e2 = bx * ay - by * ax;
+    if (e0 < 0. || e1 < 0. || e2 < 0.) && (e0 > 0. || e1 > 0. || e2 > 0.) {
+        return None;
+    }
+    let det = e0 + e1 + e2;
+    if det == 0. {
+        return None;
+    }
+
+    let t_scaled = e0 * sz * a[kz] + e1 * sz * b[kz] + e2 * sz * c[kz];
+    let t = t_scaled / det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, e1 / det, e2 / det))
+}
+
+pub fn triangle_bounding_box(vertices: &[Point3; 3]) -> AABB {
+    let mut min = vertices[0];
+    let mut max = vertices[0];
+    for v in &vertices[1..] {
+        for a in 0..3 {
+            min.e[a] = min.e[a].min(v.e[a]);
+            max.e[a] = max.e[a].max(v.e[a]);
+        }
+    }
+    AABB::new(min - BOX_PADDING, max + BOX_PADDING)
+}
+
+fn max_dimension(v: Vec3) -> usize {
+    let (x, y, z) = (v.x().abs(), v.y().abs(), v.z().abs());
+    if x > y {
+        if x > z {
+            0
+        } else {
+            2
+        }
+    } else if y > z {
+        1
+    } else {
+        2
+    }
+}
+
+impl<T> Hittable for Triangle<T>
+where
+    T: Material + Send + Sync,
+{
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, u, v) = intersect_triangle(r, &self.vertices, t_min, t_max)?;
+        let [v0, v1, v2] = self.vertices;
+        let geometric_normal = unit_vector(&cross(v1 - v0, v2 - v0));
+        let mut rec = HitRecord::new(r, r.at(t), geometric_normal, t, &self.material);
+        rec.u = u;
+        rec.v = v;
+        if let Some([n0, n1, n2]) = self.normals {
+            rec.set_shading_normal(unit_vector(&(n0 * (1. - u - v) + n1 * u + n2 * v)));
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(triangle_bounding_box(&self.vertices))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Uniformly sample the area of the triangle.
+    fn sample(&self, origin: Point3, _time: f64) -> Option<SurfaceSample> {
+        let [v0, v1, v2] = self.vertices;
+        let su = random_double().sqrt();
+        let (u, v) = (1. - su, random_double() * su);
+        let p = v0 * (1. - u - v) + v1 * u + v2 * v;
+        let normal = cross(v1 - v0, v2 - v0);
+        let area = normal.length() / 2.;
+        let normal = unit_vector(&normal);
+        let to_p = p - origin;
+        let cosine = dot(unit_vector(&to_p), normal).abs();
+        if cosine < 1e-8 {
+            return None;
+        }
+        Some(SurfaceSample {
+            p,
+            pdf: to_p.length_squared() / (cosine * area),
+        })
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
+        let r = Ray::new(origin, direction);
+        match intersect_triangle(&r, &self.vertices, 0.001, f64::INFINITY) {
+            Some((t, _, _)) => {
+                let [v0, v1, v2] = self.vertices;
+                let normal = cross(v1 - v0, v2 - v0);
+                let area = normal.length() / 2.;
+                let cosine = dot(unit_vector(&direction), unit_vector(&normal)).abs();
+                (t * t * direction.length_squared()) / (cosine * area)
+            }
+            None => 0.,
+        }
+    }
+}
+
+/// A triangle of a `TriangleMesh`, as indices into the mesh vertex arrays.
+#[derive(Copy, Clone, Debug)]
+pub struct Face {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub texcoords: Option<[usize; 3]>,
+    pub material: usize,
+}
+
+/// Vertex data and materials shared by all the triangles of a mesh.
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    /// Texture coordinates `(u, v)`
+    pub texcoords: Vec<(f64, f64)>,
+    pub faces: Vec<Face>,
+    pub materials: Vec<Box<dyn Material + Send + Sync>>,
+}
+
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [Point3; 3] {
+        let [i0, i1, i2] = self.mesh.faces[self.face].vertices;
+        [
+            self.mesh.positions[i0],
+            self.mesh.positions[i1],
+            self.mesh.positions[i2],
+        ]
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let vertices = self.vertices();
+        let (t, u, v) = intersect_triangle(r, &vertices, t_min, t_max)?;
+        let face = &self.mesh.faces[self.face];
+        let [v0, v1, v2] = vertices;
+        let geometric_normal = unit_vector(&cross(v1 - v0, v2 - v0));
+        let mat = self.mesh.materials[face.material].as_ref();
+        let mut rec = HitRecord::new(r, r.at(t), geometric_normal, t, mat);
+        rec.u = u;
+        rec.v = v;
+        if let Some([i0, i1, i2]) = face.texcoords {
+            let uvs = &self.mesh.texcoords;
+            rec.u = uvs[i0].0 * (1. - u - v) + uvs[i1].0 * u + uvs[i2].0 * v;
+            rec.v = uvs[i0].1 * (1. - u - v) + uvs[i1].1 * u + uvs[i2].1 * v;
+        }
+        if let Some([i0, i1, i2]) = face.normals {
+            let normals = &self.mesh.normals;
+            let n = normals[i0] * (1. - u - v) + normals[i1] * u + normals[i2] * v;
+            rec.set_shading_normal(unit_vector(&n));
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<AABB> {
+        Some(triangle_bounding_box(&self.vertices()))
+    }
+}
+
+/// Indexed triangle mesh with its own BVH over the faces.
+pub struct TriangleMesh {
+    bvh: LinearBVH,
+}
+
+impl TriangleMesh {
+    /// Panics if the mesh has no faces.
+    pub fn new(data: MeshData) -> TriangleMesh {
+        let mesh = Arc::new(data);
+        let faces: Vec<BoxedHittable> = (0..mesh.faces.len())
+            .map(|face| {
+                Box::new(MeshTriangle {
+                    mesh: Arc::clone(&mesh),
+                    face,
+                }) as BoxedHittable
+            })
+            .collect();
+        TriangleMesh {
+            bvh: LinearBVH::new(faces, 0., 0.),
+        }
+    }
+
+    pub fn bvh_stats(&self) -> BVHStats {
+        self.bvh.stats()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<AABB> {
+        self.bvh.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec3::{color, point3};
+
+    fn unit_triangle() -> Triangle<Lambertian> {
+        Triangle::new(
+            point3(0., 0., 0.),
+            point3(1., 0., 0.),
+            point3(0., 1., 0.),
+            Lambertian::new(color(0.5, 0.5, 0.5)),
+        )
+    }
+
+    #[test]
+    fn ray_hits_triangle() {
+        let r = Ray::new(point3(0.25, 0.25, 1.), Vec3::new(0., 0., -1.));
+        let triangle = unit_triangle();
+        let rec = triangle.hit(&r, 0.001, 100.).unwrap();
+        assert!((rec.t - 1.).abs() < 1e-12);
+        assert!(rec.front_face);
+        assert_eq!(rec.get_normal(), Vec3::new(0., 0., 1.));
+
+        let (_, u, v) = intersect_triangle(&r, &triangle.vertices, 0.001, 100.).unwrap();
+        assert!((u - 0.25).abs() < 1e-12);
+        assert!((v - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ray_misses_triangle() {
+        let r = Ray::new(point3(0.75, 0.75, 1.), Vec3::new(0., 0., -1.));
+        assert!(unit_triangle().hit(&r, 0.001, 100.).is_none());
+        let r = Ray::new(point3(0.25, 0.25, 1.), Vec3::new(0., 0., 1.));
+        assert!(unit_triangle().hit(&r, 0.001, 100.).is_none());
+    }
+
+    #[test]
+    fn shared_edge_is_watertight() {
+        // Two triangles sharing the diagonal of the unit square
+        let mat = Lambertian::new(color(0.5, 0.5, 0.5));
+        let t1 = Triangle::new(
+            point3(0., 0., 0.),
+            point3(1., 0., 0.),
+            point3(1., 1., 0.),
+            mat.clone(),
+        );
+        let t2 = Triangle::new(
+            point3(0., 0., 0.),
+            point3(1., 1., 0.),
+            point3(0., 1., 0.),
+            mat,
+        );
+        let r = Ray::new(point3(0.3, 0.3, 1.), Vec3::new(0., 0., -1.));
+        assert!(t1.hit(&r, 0.001, 100.).is_some() || t2.hit(&r, 0.001, 100.).is_some());
+    }
+
+    #[test]
+    fn flat_triangle_box_is_padded() {
+        let bbox = unit_triangle().bounding_box(0., 0.).unwrap();
+        let r = Ray::new(point3(0.25, 0.25, 1.), Vec3::new(0., 0., -1.));
+        assert!(bbox.hit(r.origin(), r.inv_direction(), 0.001, 100.));
+    }
+}
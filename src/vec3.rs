@@ -2,8 +2,6 @@ use crate::utils::{random_double, random_double_range};
 use std::fmt;
 use std::ops;
 
-const GAMMA: f64 = 2.;
-
 #[derive(Copy, Clone, Debug)]
 pub struct Vec3 {
     pub e: [f64; 3],
@@ -77,7 +75,7 @@ impl ops::Sub<f64> for Vec3 {
     type Output = Vec3;
 
     fn sub(self, other: f64) -> Vec3 {
-        self + (-1_f64 * other)
+        self + (-other)
     }
 }
 impl ops::MulAssign<f64> for Vec3 {
@@ -162,6 +160,9 @@ impl Vec3 {
     pub fn z(&self) -> f64 {
         self.e[2]
     }
+    pub fn max_component(&self) -> f64 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
         (self.e[0].abs() < s) & (self.e[1].abs() < s) & (self.e[2].abs() < s)
@@ -190,24 +191,6 @@ pub fn unit_vector(v: &Vec3) -> Vec3 {
 }
 
 #[inline]
-pub fn write_color(
-    v: Vec3,
-    sample_per_pixel: i32,
-    mut writer: impl std::io::Write,
-) -> std::io::Result<()> {
-    let scale = 1. / f64::from(sample_per_pixel);
-    writer.write_all(
-        &format!(
-            "{} {} {}\n",
-            (255.999 * (scale * v.e[0]).powf(1. / GAMMA)).floor(),
-            (255.999 * (scale * v.e[1]).powf(1. / GAMMA)).floor(),
-            (255.999 * (scale * v.e[2]).powf(1. / GAMMA)).floor()
-        )
-        .into_bytes(),
-    )?;
-    Ok(())
-}
-
 pub fn color(x: f64, y: f64, z: f64) -> Color {
     Color::new(x, y, z)
 }